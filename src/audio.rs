@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::Receiver;
+
+use hound;
+
+use cpu::CLOCK_RATE;
+use devices::sound::{InvadersSound, Sound, SoundEvent, SoundEventKind};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+// Where mixed mono 16 bit audio ends up.
+pub trait AudioSink {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+pub struct WavSink {
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+}
+
+impl WavSink {
+    pub fn new(file_name: &str, sample_rate: u32) -> io::Result<WavSink> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let writer = hound::WavWriter::create(file_name, spec).map_err(io::Error::other)?;
+
+        Ok(WavSink {
+            writer: Some(writer),
+        })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        if let Some(ref mut writer) = self.writer {
+            for &sample in samples {
+                writer.write_sample(sample).map_err(io::Error::other)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer.finalize().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+}
+
+// Raw signed 16 bit little endian samples piped into an external player,
+// e.g. "aplay -q -f S16_LE -r 44100 -c 1".
+pub struct PipeSink {
+    child: Child,
+}
+
+impl PipeSink {
+    pub fn new(command: &str) -> io::Result<PipeSink> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty audio command"))?;
+
+        let child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .spawn()?;
+
+        Ok(PipeSink { child })
+    }
+}
+
+impl AudioSink for PipeSink {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+
+        for &sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        match self.child.stdin {
+            Some(ref mut stdin) => stdin.write_all(&bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        drop(self.child.stdin.take());
+        self.child.wait().map(|_| ())
+    }
+}
+
+// Something that turns sound triggers into audio, e.g. a sample set or the synthesizer.
+pub trait SoundSource {
+    fn start(&mut self, sound: Sound);
+    fn stop(&mut self, sound: Sound);
+    // Adds the next `out.len()` samples to `out`.
+    fn render(&mut self, out: &mut [f32]);
+}
+
+// Feeds the sound events to a source at the right time and writes the result to a sink.
+pub struct AudioOutput {
+    source: Box<dyn SoundSource>,
+    events: Receiver<SoundEvent>,
+    sink: Box<dyn AudioSink>,
+
+    sample_rate: u32,
+    amplifier: bool,
+    rendered: u64,
+}
+
+impl AudioOutput {
+    pub fn new(sound: &mut InvadersSound, source: Box<dyn SoundSource>, sink: Box<dyn AudioSink>, sample_rate: u32) -> AudioOutput {
+        AudioOutput {
+            source,
+            events: sound.subscribe(),
+            sink,
+
+            sample_rate,
+            amplifier: sound.amplifier(),
+            rendered: 0,
+        }
+    }
+
+    // Renders everything up to the given CPU cycle.
+    pub fn update(&mut self, cycle: u64) -> io::Result<()> {
+        let events: Vec<SoundEvent> = self.events.try_iter().collect();
+
+        for event in events {
+            self.render(event.cycle)?;
+
+            match event.kind {
+                SoundEventKind::Start(sound) => self.source.start(sound),
+                SoundEventKind::Stop(sound) => self.source.stop(sound),
+                SoundEventKind::Amplifier(enabled) => self.amplifier = enabled,
+                SoundEventKind::Flip(_) => (),
+            }
+        }
+
+        self.render(cycle)
+    }
+
+    pub fn finish(&mut self, cycle: u64) -> io::Result<()> {
+        self.update(cycle)?;
+        self.sink.finish()
+    }
+
+    fn render(&mut self, cycle: u64) -> io::Result<()> {
+        let target = cycle * self.sample_rate as u64 / CLOCK_RATE;
+
+        if target <= self.rendered {
+            return Ok(());
+        }
+
+        let mut mix = vec![0.0f32; (target - self.rendered) as usize];
+        self.rendered = target;
+        self.source.render(&mut mix);
+
+        let amplifier = self.amplifier;
+        let output: Vec<i16> = mix.iter()
+            .map(|&value| if amplifier { value } else { 0.0 })
+            .map(|value| (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect();
+
+        self.sink.write(&output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use io::IoDevice;
+    use samples::SamplePlayer;
+
+    const SAMPLE_RATE: u32 = 120;
+    const CYCLES_PER_SAMPLE: u64 = CLOCK_RATE / SAMPLE_RATE as u64;
+
+    fn write_wav(path: &Path, value: i16, length: usize) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+
+        for _ in 0..length {
+            writer.write_sample(value).unwrap();
+        }
+
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn port_edges_are_mixed_into_the_wav() {
+        let directory = env::temp_dir().join(format!("r8080-{}-samples", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        // Shot and the first fleet step.
+        write_wav(&directory.join("1.wav"), 0x4000, 4);
+        write_wav(&directory.join("4.wav"), 0x2000, 2);
+
+        let out = directory.join("out.wav");
+        let mut sound = InvadersSound::new();
+        let player = SamplePlayer::load(directory.to_str().unwrap(), SAMPLE_RATE);
+        let sink = WavSink::new(out.to_str().unwrap(), SAMPLE_RATE).unwrap();
+        let mut output = AudioOutput::new(&mut sound, Box::new(player), Box::new(sink), SAMPLE_RATE);
+
+        // Amplifier and shot on, fleet step 10 samples later, amplifier off after 16.
+        sound.output(0x03, 0x22, 0);
+        sound.output(0x03, 0x20, CYCLES_PER_SAMPLE);
+        sound.output(0x05, 0x01, 10 * CYCLES_PER_SAMPLE);
+        sound.output(0x05, 0x00, 11 * CYCLES_PER_SAMPLE);
+        sound.output(0x05, 0x01, 14 * CYCLES_PER_SAMPLE);
+        sound.output(0x03, 0x00, 15 * CYCLES_PER_SAMPLE);
+        output.finish(20 * CYCLES_PER_SAMPLE).unwrap();
+
+        let samples: Vec<i16> = hound::WavReader::open(&out).unwrap()
+            .samples::<i16>()
+            .map(|sample| sample.unwrap())
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+
+        // One shot samples play out after their bit is cleared.
+        let shot = (0.5 * i16::MAX as f32) as i16;
+        let fleet = (0.25 * i16::MAX as f32) as i16;
+        let mut expected = vec![0; 20];
+        expected[..4].copy_from_slice(&[shot; 4]);
+        expected[10..12].copy_from_slice(&[fleet; 2]);
+        expected[14] = fleet;
+
+        assert_eq!(samples, expected);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use minifb::{Key, Window};
+
+use config::Config;
+use devices::panel::{Button, BUTTONS};
+
+const KEYS: [Key; 106] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+    Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10,
+    Key::F11, Key::F12, Key::F13, Key::F14, Key::F15,
+    Key::Down, Key::Left, Key::Right, Key::Up,
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal, Key::LeftBracket, Key::Minus,
+    Key::Period, Key::RightBracket, Key::Semicolon, Key::Slash,
+    Key::Backspace, Key::Delete, Key::End, Key::Enter, Key::Escape, Key::Home, Key::Insert, Key::Menu,
+    Key::PageDown, Key::PageUp, Key::Pause, Key::Space, Key::Tab,
+    Key::NumLock, Key::CapsLock, Key::ScrollLock, Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadDot, Key::NumPadSlash, Key::NumPadAsterisk, Key::NumPadMinus, Key::NumPadPlus, Key::NumPadEnter,
+    Key::LeftAlt, Key::RightAlt, Key::LeftSuper, Key::RightSuper,
+];
+
+// Key names as minifb spells them, ignoring case, e.g. "Space", "LeftCtrl" or "Key1".
+pub fn parse_key(name: &str) -> Option<Key> {
+    KEYS.iter().cloned().find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+// Which keys press which button on the control panel.
+pub struct Bindings {
+    pub keys: Vec<(Key, Button)>,
+}
+
+impl Bindings {
+    pub fn new() -> Bindings {
+        Bindings {
+            keys: vec![
+                (Key::C, Button::Coin),
+                (Key::X, Button::P1Start),
+                (Key::V, Button::P2Start),
+                (Key::Z, Button::P1Fire),
+                (Key::Left, Button::P1Left),
+                (Key::Right, Button::P1Right),
+                (Key::S, Button::P2Fire),
+                (Key::A, Button::P2Left),
+                (Key::D, Button::P2Right),
+                (Key::T, Button::Tilt),
+            ],
+        }
+    }
+
+    // The defaults, then [keys.default] and [keys.<game>] from the config on top.
+    pub fn configured(game: &str, config: &Config) -> Result<Bindings, String> {
+        let mut bindings = Bindings::new();
+
+        for section in ["default", game].iter() {
+            if let Some(table) = config.keys.get(*section) {
+                bindings.apply(table).map_err(|e| format!("Invalid key bindings in [keys.{}]: {}", section, e))?;
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    // Every button named in the table gets exactly the keys listed for it,
+    // the others keep their bindings.
+    pub fn apply(&mut self, table: &HashMap<String, Vec<String>>) -> Result<(), String> {
+        for (name, keys) in table.iter() {
+            let button = Button::parse(name).ok_or_else(|| format!("Unknown input {:?}", name))?;
+
+            self.keys.retain(|&(_, bound)| bound != button);
+
+            for key in keys {
+                let key = parse_key(key).ok_or_else(|| format!("Unknown key {:?} for {}", key, name))?;
+                self.keys.push((key, button));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn held(&self, window: &Window) -> Vec<Button> {
+        self.keys.iter()
+            .filter(|&&(key, _)| window.is_key_down(key))
+            .map(|&(_, button)| button)
+            .collect()
+    }
+
+    pub fn list(&self) -> String {
+        let mut out = String::new();
+
+        for &(button, name) in BUTTONS.iter() {
+            let keys: Vec<String> = self.keys.iter()
+                .filter(|&&(_, bound)| bound == button)
+                .map(|&(key, _)| format!("{:?}", key))
+                .collect();
+
+            out.push_str(&format!("{:<10} {}\n", name, keys.join(", ")));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    fn keys_for(bindings: &Bindings, button: Button) -> Vec<Key> {
+        bindings.keys.iter().filter(|&&(_, bound)| bound == button).map(|&(key, _)| key).collect()
+    }
+
+    #[test]
+    fn key_names_ignore_case() {
+        assert_eq!(parse_key("space"), Some(Key::Space));
+        assert_eq!(parse_key("LEFTCTRL"), Some(Key::LeftCtrl));
+        assert_eq!(parse_key("Key1"), Some(Key::Key1));
+        assert_eq!(parse_key("1"), None);
+        assert_eq!(parse_key("Hyper"), None);
+    }
+
+    #[test]
+    fn apply_replaces_only_named_buttons() {
+        let mut bindings = Bindings::new();
+        let mut table = HashMap::new();
+        table.insert(String::from("p1_fire"), vec![String::from("Space"), String::from("Up")]);
+
+        bindings.apply(&table).unwrap();
+
+        assert_eq!(keys_for(&bindings, Button::P1Fire), vec![Key::Space, Key::Up]);
+        assert_eq!(keys_for(&bindings, Button::Coin), vec![Key::C]);
+    }
+
+    #[test]
+    fn apply_rejects_unknown_names() {
+        let mut table = HashMap::new();
+        table.insert(String::from("p1_fire"), vec![String::from("Hyper")]);
+        assert!(Bindings::new().apply(&table).unwrap_err().contains("Unknown key \"Hyper\""));
+
+        let mut table = HashMap::new();
+        table.insert(String::from("p3_fire"), vec![String::from("Space")]);
+        assert!(Bindings::new().apply(&table).unwrap_err().contains("Unknown input \"p3_fire\""));
+    }
+
+    #[test]
+    fn game_section_overrides_default_section() {
+        let config: Config = toml::from_str(r#"
+            [keys.default]
+            p1_fire = ["Space"]
+            coin = ["Key5"]
+
+            [keys.invaders]
+            p1_fire = ["LeftCtrl"]
+        "#).unwrap();
+
+        let invaders = Bindings::configured("invaders", &config).unwrap();
+        assert_eq!(keys_for(&invaders, Button::P1Fire), vec![Key::LeftCtrl]);
+        assert_eq!(keys_for(&invaders, Button::Coin), vec![Key::Key5]);
+
+        let other = Bindings::configured("lrescue", &config).unwrap();
+        assert_eq!(keys_for(&other, Button::P1Fire), vec![Key::Space]);
+    }
+
+    #[test]
+    fn configured_names_the_bad_section() {
+        let config: Config = toml::from_str("[keys.invaders]\np1_fire = [\"Nope\"]\n").unwrap();
+
+        assert!(Bindings::configured("invaders", &config).err().unwrap().contains("[keys.invaders]"));
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Call,
+    Rst,
+    Interrupt,
+    Pchl,
+}
+
+#[derive(Default)]
+pub struct Edge {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+struct Frame {
+    function: Option<u16>,
+    // Stack pointer right after the return address was pushed. The frame is
+    // left as soon as the stack pointer moves above it, which also covers
+    // return addresses discarded with POP or INX SP.
+    sp: u32,
+    entry_cycles: u64,
+    path: usize,
+    kind: EdgeKind,
+}
+
+// Shadow call stack that attributes cycles to subroutines. `None` is the root,
+// i.e. whatever was running before the first tracked call.
+pub struct CallGraph {
+    pub exclusive: HashMap<Option<u16>, u64>,
+    pub inclusive: HashMap<Option<u16>, u64>,
+    pub edges: HashMap<(Option<u16>, Option<u16>, EdgeKind), Edge>,
+    pub total_cycles: u64,
+
+    stack: Vec<Frame>,
+    // Active frames per function, so recursion only counts the outermost one.
+    depth: HashMap<Option<u16>, u32>,
+    paths: Vec<(usize, Option<u16>)>,
+    path_ids: HashMap<(usize, Option<u16>), usize>,
+    path_cycles: Vec<u64>,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        let root = Frame {
+            function: None,
+            sp: u32::MAX,
+            entry_cycles: 0,
+            path: 0,
+            kind: EdgeKind::Call,
+        };
+
+        CallGraph {
+            exclusive: HashMap::new(),
+            inclusive: HashMap::new(),
+            edges: HashMap::new(),
+            total_cycles: 0,
+
+            stack: vec![root],
+            depth: HashMap::new(),
+            paths: vec![(0, None)],
+            path_ids: HashMap::new(),
+            path_cycles: vec![0],
+        }
+    }
+
+    // Called after every instruction with the state before and after it.
+    pub fn record(&mut self, opcode: u8, pc: u16, sp_before: u16, sp: u16, cycles: u64) {
+        self.total_cycles += cycles;
+
+        {
+            let top = self.stack.last().unwrap();
+            *self.exclusive.entry(top.function).or_insert(0) += cycles;
+            self.path_cycles[top.path] += cycles;
+        }
+
+        self.leave(sp);
+
+        let pushed = sp == sp_before.wrapping_sub(2);
+
+        match opcode {
+            0xCD | 0xDD | 0xED | 0xFD |
+            0xC4 | 0xCC | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC if pushed => self.enter(pc, sp, EdgeKind::Call),
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF if pushed => self.enter(pc, sp, EdgeKind::Rst),
+            0xE9 => self.dispatch(pc, sp),
+            _ => (),
+        }
+    }
+
+    pub fn interrupt(&mut self, vector: u16, sp: u16) {
+        self.enter(vector, sp, EdgeKind::Interrupt);
+    }
+
+    fn enter(&mut self, function: u16, sp: u16, kind: EdgeKind) {
+        let parent = self.stack.last().unwrap().path;
+        let path = self.path_id(parent, Some(function));
+        *self.depth.entry(Some(function)).or_insert(0) += 1;
+
+        self.stack.push(Frame {
+            function: Some(function),
+            sp: sp as u32,
+            entry_cycles: self.total_cycles,
+            path,
+            kind,
+        });
+    }
+
+    // PCHL is mostly used for jump tables, so the target is treated as a tail
+    // call that returns together with the frame that dispatched to it.
+    fn dispatch(&mut self, function: u16, sp: u16) {
+        let replace = {
+            let top = self.stack.last().unwrap();
+            top.kind == EdgeKind::Pchl && top.sp == sp as u32
+        };
+
+        if replace {
+            self.pop();
+        }
+
+        self.enter(function, sp, EdgeKind::Pchl);
+    }
+
+    fn leave(&mut self, sp: u16) {
+        while self.stack.len() > 1 && self.stack.last().unwrap().sp < sp as u32 {
+            self.pop();
+        }
+    }
+
+    fn pop(&mut self) {
+        let frame = self.stack.pop().unwrap();
+        let caller = self.stack.last().unwrap().function;
+        let cycles = self.total_cycles - frame.entry_cycles;
+
+        let depth = self.depth.get_mut(&frame.function).unwrap();
+        *depth -= 1;
+
+        if *depth == 0 {
+            *self.inclusive.entry(frame.function).or_insert(0) += cycles;
+        }
+
+        let edge = self.edges.entry((caller, frame.function, frame.kind)).or_default();
+        edge.count += 1;
+        edge.cycles += cycles;
+    }
+
+    fn path_id(&mut self, parent: usize, function: Option<u16>) -> usize {
+        if let Some(&id) = self.path_ids.get(&(parent, function)) {
+            return id;
+        }
+
+        let id = self.paths.len();
+        self.paths.push((parent, function));
+        self.path_cycles.push(0);
+        self.path_ids.insert((parent, function), id);
+
+        id
+    }
+
+    // Frames that are still on the shadow stack count up to the current cycle.
+    fn inclusive_cycles(&self, function: Option<u16>) -> u64 {
+        if function.is_none() {
+            return self.total_cycles;
+        }
+
+        let active = self.stack.iter()
+            .find(|frame| frame.function == function)
+            .map_or(0, |frame| self.total_cycles - frame.entry_cycles);
+
+        self.inclusive.get(&function).unwrap_or(&0) + active
+    }
+
+    pub fn dot(&self) -> String {
+        let mut out = String::from("digraph callgraph {\n    node [shape=box];\n");
+
+        let mut functions: Vec<_> = self.exclusive.keys().chain(self.inclusive.keys()).cloned().collect();
+        functions.sort();
+        functions.dedup();
+
+        for function in functions {
+            out.push_str(&format!("    \"{}\" [label=\"{}\\nexclusive: {}\\ninclusive: {}\"];\n",
+                name(function),
+                name(function),
+                self.exclusive.get(&function).unwrap_or(&0),
+                self.inclusive_cycles(function)));
+        }
+
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort_by_key(|&(key, _)| *key);
+
+        for (&(caller, callee, kind), edge) in edges {
+            let kind = match kind {
+                EdgeKind::Call => "call",
+                EdgeKind::Rst => "rst",
+                EdgeKind::Interrupt => "interrupt",
+                EdgeKind::Pchl => "pchl",
+            };
+
+            out.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{} x{}\\n{} cycles\"];\n",
+                name(caller), name(callee), kind, edge.count, edge.cycles));
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    // Exclusive cycles per call stack in the collapsed format used by flamegraph tools.
+    pub fn collapsed(&self) -> String {
+        let mut lines = Vec::new();
+
+        for (id, &cycles) in self.path_cycles.iter().enumerate() {
+            if cycles == 0 {
+                continue;
+            }
+
+            let mut frames = Vec::new();
+            let mut current = id;
+
+            loop {
+                let (parent, function) = self.paths[current];
+                frames.push(name(function));
+
+                if current == 0 {
+                    break;
+                }
+
+                current = parent;
+            }
+
+            frames.reverse();
+            lines.push(format!("{} {}", frames.join(";"), cycles));
+        }
+
+        lines.sort();
+
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    pub fn write(&self, file_name: &str) -> io::Result<()> {
+        File::create(format!("{}.dot", file_name))?.write_all(self.dot().as_bytes())?;
+        File::create(format!("{}.folded", file_name))?.write_all(self.collapsed().as_bytes())
+    }
+}
+
+fn name(function: Option<u16>) -> String {
+    match function {
+        Some(address) => format!("sub_{:04x}", address),
+        None => String::from("root"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALL: u8 = 0xCD;
+    const RET: u8 = 0xC9;
+    const NOP: u8 = 0x00;
+
+    #[test]
+    fn recursion_counts_outermost_frame_once() {
+        let mut graph = CallGraph::new();
+
+        // root calls 0x100, which calls itself once before both return.
+        graph.record(CALL, 0x100, 0x2400, 0x23fe, 17);
+        graph.record(NOP, 0x101, 0x23fe, 0x23fe, 4);
+        graph.record(CALL, 0x100, 0x23fe, 0x23fc, 17);
+        graph.record(NOP, 0x101, 0x23fc, 0x23fc, 4);
+        graph.record(RET, 0x103, 0x23fc, 0x23fe, 10);
+        graph.record(RET, 0x003, 0x23fe, 0x2400, 10);
+
+        assert_eq!(graph.inclusive[&Some(0x100)], 45);
+        assert_eq!(graph.inclusive_cycles(Some(0x100)), 45);
+        assert_eq!(graph.exclusive[&Some(0x100)], 45);
+        assert_eq!(graph.edges[&(Some(0x100), Some(0x100), EdgeKind::Call)].count, 1);
+    }
+
+    #[test]
+    fn active_recursion_counts_outermost_frame_once() {
+        let mut graph = CallGraph::new();
+
+        graph.record(CALL, 0x100, 0x2400, 0x23fe, 17);
+        graph.record(CALL, 0x100, 0x23fe, 0x23fc, 17);
+        graph.record(NOP, 0x101, 0x23fc, 0x23fc, 4);
+
+        assert_eq!(graph.inclusive_cycles(Some(0x100)), 21);
+    }
+}
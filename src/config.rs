@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use toml;
+
+// Settings read from a TOML file. Anything given on the command line wins.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub dip: DipConfig,
+
+    // Key bindings per game, with "default" applying to every game, e.g.
+    // [keys.invaders]
+    // p1_fire = ["Space"]
+    #[serde(default)]
+    pub keys: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DipConfig {
+    pub ships: Option<u8>,
+    pub extra_ship_at: Option<u16>,
+    pub coin_info: Option<bool>,
+    pub tilt: Option<bool>,
+    pub cocktail: Option<bool>,
+}
+
+impl Config {
+    pub fn load(file_name: &str) -> io::Result<Config> {
+        let mut text = String::new();
+        File::open(file_name)?.read_to_string(&mut text)?;
+
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // $XDG_CONFIG_HOME/r8080/config.toml, falling back to ~/.config.
+    pub fn user_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(base.join("r8080").join("config.toml"))
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::{self, Read, Write, BufWriter};
+use png;
+
+use opcode::{Opcode, disassemble};
+use ram::{Sram, RAM_SIZE};
+
+pub const EXECUTED: u8 = 1 << 0;
+pub const OPERAND: u8 = 1 << 1;
+pub const READ: u8 = 1 << 2;
+pub const WRITTEN: u8 = 1 << 3;
+
+const MAGIC: &[u8; 8] = b"R8080COV";
+const HEATMAP_SCALE: usize = 2;
+
+// Per address record of how the byte was ever accessed.
+pub struct Coverage {
+    pub flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            flags: vec![0; RAM_SIZE],
+        }
+    }
+
+    pub fn mark(&mut self, address: u16, flag: u8) {
+        self.flags[address as usize] |= flag;
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for (flags, other) in self.flags.iter_mut().zip(other.flags.iter()) {
+            *flags |= *other;
+        }
+    }
+
+    pub fn load(file_name: &str) -> io::Result<Coverage> {
+        let mut f = File::open(file_name)?;
+
+        let mut magic = [0; 8];
+        f.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a r8080 coverage file"));
+        }
+
+        let mut coverage = Coverage::new();
+        f.read_exact(&mut coverage.flags)?;
+
+        Ok(coverage)
+    }
+
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        let mut f = File::create(file_name)?;
+
+        f.write_all(MAGIC)?;
+        f.write_all(&self.flags)
+    }
+
+    // Instructions are only decoded where execution was actually seen, everything
+    // else is listed as data. Runs of untouched zero bytes are collapsed.
+    pub fn annotated_disassembly(&self, ram: &Sram) -> String {
+        let mut out = String::new();
+        let mut address = 0;
+
+        while address < RAM_SIZE {
+            let flags = self.flags[address];
+            let value = ram.peek_byte(address as u16);
+
+            if flags == 0 && value == 0 {
+                let start = address;
+
+                while address < RAM_SIZE && self.flags[address] == 0 && ram.peek_byte(address as u16) == 0 {
+                    address += 1;
+                }
+
+                if address - start > 1 {
+                    out.push_str(&format!("; {:#06x}-{:#06x} untouched\n", start, address - 1));
+                    continue;
+                }
+
+                address = start;
+            }
+
+            if flags & EXECUTED != 0 {
+                let length = Opcode::new(value).length() as usize;
+                let mut bytes = String::new();
+
+                for offset in 0..length {
+                    bytes.push_str(&format!("{:02x} ", ram.peek_byte((address + offset) as u16)));
+                }
+
+                out.push_str(&format!("{:04x}  {} {:<9} {}\n",
+                    address, describe(flags), bytes, disassemble(ram, address as u16)));
+
+                address += length;
+            } else {
+                out.push_str(&format!("{:04x}  {} {:02x}        DB ${:02x}\n",
+                    address, describe(flags), value, value));
+
+                address += 1;
+            }
+        }
+
+        out
+    }
+
+    // One pixel per address, 256 addresses per row. Bytes that hold something
+    // but were never executed or accessed are highlighted.
+    pub fn write_heatmap(&self, ram: &Sram, file_name: &str) -> io::Result<()> {
+        let size = 256 * HEATMAP_SCALE;
+        let mut data = vec![0; size * size * 3];
+
+        for address in 0..RAM_SIZE {
+            let color = color(self.flags[address], ram.peek_byte(address as u16));
+
+            let x = (address & 0xff) * HEATMAP_SCALE;
+            let y = (address >> 8) * HEATMAP_SCALE;
+
+            for dy in 0..HEATMAP_SCALE {
+                for dx in 0..HEATMAP_SCALE {
+                    let offset = ((y + dy) * size + x + dx) * 3;
+                    data[offset..offset + 3].copy_from_slice(&color);
+                }
+            }
+        }
+
+        let f = BufWriter::new(File::create(file_name)?);
+        let mut encoder = png::Encoder::new(f, size as u32, size as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)
+    }
+}
+
+fn describe(flags: u8) -> String {
+    let mut out = String::new();
+
+    out.push(if flags & EXECUTED != 0 { 'x' } else { '-' });
+    out.push(if flags & OPERAND != 0 { 'o' } else { '-' });
+    out.push(if flags & READ != 0 { 'r' } else { '-' });
+    out.push(if flags & WRITTEN != 0 { 'w' } else { '-' });
+
+    out
+}
+
+fn color(flags: u8, value: u8) -> [u8; 3] {
+    if flags & EXECUTED != 0 {
+        [0x00, 0xe0, 0x00]
+    } else if flags & OPERAND != 0 {
+        [0x00, 0x80, 0x00]
+    } else if flags & WRITTEN != 0 {
+        [0xe0, 0x00, 0x00]
+    } else if flags & READ != 0 {
+        [0x20, 0x60, 0xff]
+    } else if value != 0 {
+        [0xff, 0xa0, 0x00]
+    } else {
+        [0x00, 0x00, 0x00]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("r8080-{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let path = temp_path("round-trip.cov");
+        let mut coverage = Coverage::new();
+        coverage.mark(0x0000, EXECUTED);
+        coverage.mark(0x1fff, READ | WRITTEN);
+
+        coverage.save(&path).unwrap();
+        let loaded = Coverage::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.flags == coverage.flags);
+    }
+
+    #[test]
+    fn load_rejects_other_files() {
+        let path = temp_path("bad.cov");
+        fs::write(&path, b"not coverage").unwrap();
+
+        let error = Coverage::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(Coverage::load(&temp_path("missing.cov")).err().unwrap().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn merge_combines_flags() {
+        let mut coverage = Coverage::new();
+        coverage.mark(0x10, EXECUTED);
+
+        let mut other = Coverage::new();
+        other.mark(0x10, OPERAND);
+        other.mark(0x20, WRITTEN);
+
+        coverage.merge(&other);
+
+        assert_eq!(coverage.flags[0x10], EXECUTED | OPERAND);
+        assert_eq!(coverage.flags[0x20], WRITTEN);
+        assert_eq!(coverage.flags[0x30], 0);
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::Write;
+
+use cpu::{Cpu, StopReason};
+use ram::RAM_SIZE;
+
+// Just enough CP/M to run .COM test programs: console output through the
+// BDOS entry point, and a warm boot ends the run.
+
+pub const TPA: u16 = 0x0100;
+
+const WARM_BOOT: u16 = 0x0000;
+const BDOS: u16 = 0x0005;
+// Programs read the top of usable memory from the BDOS jump at 0x0006.
+const MEMORY_TOP: u16 = 0xf000;
+
+const C_WRITE: u8 = 2;
+const C_WRITESTR: u8 = 9;
+
+impl Cpu {
+    pub fn boot_cpm(&mut self) {
+        self.ram.load_bytes(&[0xc3, MEMORY_TOP as u8, (MEMORY_TOP >> 8) as u8], BDOS);
+        self.ram.load_bytes(&[0xc9], MEMORY_TOP);
+
+        self.bdos = true;
+        self.sp = MEMORY_TOP;
+        // Returning from the program warm boots.
+        self.push_stack(WARM_BOOT);
+        self.move_pc(TPA);
+    }
+
+    pub fn bdos_hook(&mut self) {
+        match self.pc {
+            WARM_BOOT => {
+                let _ = writeln!(self.console);
+                self.stop = Some(StopReason::WarmBoot);
+            },
+            BDOS => self.bdos_call(),
+            _ => (),
+        }
+    }
+
+    fn bdos_call(&mut self) {
+        match self.c {
+            C_WRITE => {
+                let _ = self.console.write_all(&[self.e]);
+            },
+            C_WRITESTR => {
+                let mut address = (self.d as u16) << 8 | self.e as u16;
+
+                for _ in 0..RAM_SIZE {
+                    let byte = self.ram.peek_byte(address);
+
+                    if byte == b'$' {
+                        break;
+                    }
+
+                    let _ = self.console.write_all(&[byte]);
+                    address = address.wrapping_add(1);
+                }
+            },
+            _ => (),
+        }
+
+        let _ = self.console.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use ram::Sram;
+
+    // Shares what the program prints with the test.
+    #[derive(Clone)]
+    struct Console(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Console {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(program: &[u8]) -> (StopReason, String) {
+        let mut ram = Sram::new();
+        ram.load_bytes(program, TPA);
+
+        let console = Console(Rc::new(RefCell::new(Vec::new())));
+        let mut cpu = Cpu::headless(ram);
+        cpu.console = Box::new(console.clone());
+        cpu.max_instructions = Some(1000);
+        cpu.boot_cpm();
+
+        let reason = cpu.run();
+        let output = String::from_utf8(console.0.borrow().clone()).unwrap();
+
+        (reason, output)
+    }
+
+    #[test]
+    fn c_write_prints_the_character_in_e() {
+        // MVI C,2; MVI E,'A'; CALL 5; MVI E,'B'; CALL 5; RET
+        let (reason, output) = run(&[0x0e, 0x02, 0x1e, b'A', 0xcd, 0x05, 0x00, 0x1e, b'B', 0xcd, 0x05, 0x00, 0xc9]);
+
+        assert_eq!(reason, StopReason::WarmBoot);
+        assert_eq!(output, "AB\n");
+    }
+
+    #[test]
+    fn c_writestr_prints_up_to_the_dollar() {
+        // MVI C,9; LXI D,0x0109; CALL 5; RET; "Hello$ignored"
+        let mut program = vec![0x0e, 0x09, 0x11, 0x09, 0x01, 0xcd, 0x05, 0x00, 0xc9];
+        program.extend_from_slice(b"Hello$ignored");
+
+        let (reason, output) = run(&program);
+
+        assert_eq!(reason, StopReason::WarmBoot);
+        assert_eq!(output, "Hello\n");
+    }
+
+    #[test]
+    fn other_calls_print_nothing() {
+        // MVI C,1; CALL 5; RET
+        let (reason, output) = run(&[0x0e, 0x01, 0xcd, 0x05, 0x00, 0xc9]);
+
+        assert_eq!(reason, StopReason::WarmBoot);
+        assert_eq!(output, "\n");
+    }
+}
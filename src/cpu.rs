@@ -1,13 +1,31 @@
+use std::cell::RefCell;
 use std::fmt;
-use ram::Sram;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::panic;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use ram::{Sram, MemoryDump};
 use util::*;
 use opcode::Opcode;
+use trace::{Trace, TraceEntry, DEFAULT_TRACE_DEPTH};
+use profiler::Profiler;
+use callgraph::CallGraph;
+use memview::MemoryViewer;
+use io::IoBus;
+use devices::sound::{InvadersSound, SoundLog};
+use devices::watchdog::{Watchdog, WatchdogAction};
+use devices::panel::ControlPanel;
+use devices::colorram::{self, ColorRam};
+use bindings::Bindings;
+use manifest::Orientation;
+use audio::AudioOutput;
+use overlay::{Overlay, Tint, BLACK, WHITE};
 
 use instructions::*;
 
 use std::{thread, time};
-use minifb::{Key, WindowOptions, Window};
-use byteorder::{BigEndian, ReadBytesExt};
+use minifb::{Key, Scale, WindowOptions, Window};
 
 const REG_BC: u8 = 0;
 const REG_DE: u8 = 1;
@@ -27,9 +45,37 @@ const FLAG_S: u8 = 1 << 7;
 const INT_END: u16 = 0x08;
 const INT_MID: u16 = 0x10;
 
+// One interrupt every half frame at 60 frames per second.
+pub const CYCLES_PER_INTERRUPT: u32 = 16667;
+pub const CLOCK_RATE: u64 = CYCLES_PER_INTERRUPT as u64 * 120;
+
+// Writes the memory dump while the game is running.
+const DUMP_KEY: Key = Key::F12;
+
 const WIDTH: usize = 224;
 const HEIGHT: usize = 256;
 
+// Why `run` returned. Faults in the emulator itself still panic and leave a
+// crash dump.
+#[derive(Clone, PartialEq, Debug)]
+pub enum StopReason {
+    WindowClosed,
+    Limit(String),
+    // A memory or port access with the stop policy.
+    Violation(String),
+    Watchdog,
+    // A CP/M program returned to 0x0000.
+    WarmBoot,
+    // An output file could not be written.
+    Error(String),
+}
+
+impl StopReason {
+    pub fn is_error(&self) -> bool {
+        matches!(*self, StopReason::Violation(_) | StopReason::Watchdog | StopReason::Error(_))
+    }
+}
+
 #[allow(dead_code)]
 pub struct Cpu {
     pub a: u8,
@@ -47,34 +93,69 @@ pub struct Cpu {
     pub ram: Sram,
 
     pub cycles: u32,
+    pub total_cycles: u64,
     pub instruction_count: u64,
 
     pub current_opcode: u8,
     pub last_interrupt: u16,
     pub last_interrupt_time: time::Instant,
 
-    pub window: Window,
+    // None when running headless, e.g. for CP/M programs.
+    pub window: Option<Window>,
+    pub orientation: Orientation,
+    pub tint: Tint,
+    pub bindings: Bindings,
     
-    pub port4hi: u8,
-    pub port4lo: u8,
-    pub port2: u8,
-    pub inp1: u8,
-    pub inp2: u8,
-    pub port3o: u8,
-    pub port5o: u8,
+    pub io: IoBus,
+    pub sound: Option<Rc<RefCell<InvadersSound>>>,
+    pub watchdog: Option<Rc<RefCell<Watchdog>>>,
+    pub panel: Option<Rc<RefCell<ControlPanel>>>,
+    pub color_ram: Option<Rc<RefCell<ColorRam>>>,
 
     pub interrupt_in_progress: bool,
+    pub stop: Option<StopReason>,
+    pub half_frames: u64,
+    pub max_instructions: Option<u64>,
+    pub max_frames: Option<u64>,
+    // Emulate the CP/M BDOS console calls for .COM programs.
+    pub bdos: bool,
+    pub console: Box<dyn Write>,
+
+    pub trace: Trace,
+    pub profiler: Option<Profiler>,
+    pub call_graph: Option<CallGraph>,
+    pub memory_viewer: Option<MemoryViewer>,
+    pub sound_log: Option<SoundLog>,
+    pub audio: Option<AudioOutput>,
+    pub memory_dump: Option<MemoryDump>,
+    pub dump_at_frame: Option<u64>,
+    dump_key_held: bool,
 }
 
 impl Cpu {
-    pub fn new(ram: Sram) -> Cpu {
-        let window = Window::new("Space Invaders",
-                                 WIDTH,
-                                 HEIGHT,
-                                 WindowOptions::default()).unwrap_or_else(|e| {
+    pub fn new(ram: Sram, title: &str, orientation: Orientation, scale: Scale) -> Cpu {
+        let (width, height) = match orientation {
+            Orientation::Rot0 => (HEIGHT, WIDTH),
+            Orientation::Rot270 => (WIDTH, HEIGHT),
+        };
+
+        let window = Window::new(title,
+                                 width,
+                                 height,
+                                 WindowOptions { scale, ..WindowOptions::default() }).unwrap_or_else(|e| {
             panic!("{}", e);
         });
 
+        Cpu {
+            window: Some(window),
+            orientation,
+            tint: Tint::monochrome(width, height),
+            ..Cpu::headless(ram)
+        }
+    }
+
+    // No window, no input and no pacing to real time.
+    pub fn headless(ram: Sram) -> Cpu {
         Cpu {
             a: 0x00,
             f: 0x00,
@@ -90,6 +171,7 @@ impl Cpu {
 
             ram: ram,
             cycles: 0,
+            total_cycles: 0,
             instruction_count: 0,
             
             current_opcode: 0x00,
@@ -97,63 +179,143 @@ impl Cpu {
             last_interrupt: INT_MID,
             last_interrupt_time: time::Instant::now(),
 
-            window: window,
+            window: None,
+            orientation: Orientation::Rot270,
+            tint: Tint::monochrome(WIDTH, HEIGHT),
+            bindings: Bindings::new(),
 
-            port4hi: 0x00,
-            port4lo: 0x00,
-            port2: 0x00,
-            inp1: 0x00,
-            inp2: 0x00,
-            port3o: 0x00,
-            port5o: 0x00,
+            io: IoBus::new(),
+            sound: None,
+            watchdog: None,
+            panel: None,
+            color_ram: None,
 
             interrupt_in_progress: false,
+            stop: None,
+            half_frames: 0,
+            max_instructions: None,
+            max_frames: None,
+            bdos: false,
+            console: Box::new(io::stdout()),
+
+            trace: Trace::new(DEFAULT_TRACE_DEPTH),
+            profiler: None,
+            call_graph: None,
+            memory_viewer: None,
+            sound_log: None,
+            audio: None,
+            memory_dump: None,
+            dump_at_frame: None,
+            dump_key_held: false,
         }
     }
 }
 
 impl Cpu {
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> StopReason {
         loop {
-            self.check_interrupt();
-
-            let opcode = Opcode::new(self.ram.read_byte(self.pc));
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| self.step()));
+
+            match result {
+                Ok(Some(reason)) => return reason,
+                Ok(None) => (),
+                Err(e) => {
+                    self.crash_dump();
+                    panic::resume_unwind(e);
+                },
+            }
 
-            /*
-            if opcode.opcode == 0x76 {
-                println!("HALT at {:#06x}", self.pc);
+            if let Some(limit) = self.limit_reached() {
+                println!("Stopped after {} at {:#06x}", limit, self.pc);
+                return StopReason::Limit(limit);
             }
+        }
+    }
 
-            if self.pc == 0x0005 {
-                if self.c == 9 {
-                    let addr = self.read_dword(REG_DE);
+    fn limit_reached(&self) -> Option<String> {
+        match (self.max_instructions, self.max_frames) {
+            (Some(max), _) if self.instruction_count >= max => Some(format!("{} instructions", self.instruction_count)),
+            (_, Some(max)) if self.half_frames / 2 >= max => Some(format!("{} frames", self.half_frames / 2)),
+            _ => None,
+        }
+    }
 
-                    for x in 0..10 {
-                        println!("{:?}", self.ram.read_byte(addr+x) as char);
-                    }
-                }
+    pub fn step(&mut self) -> Option<StopReason> {
+        self.check_interrupt();
 
-                if self.c == 2 {
-                    println!("{:?}", self.e as char);
-                }
-            }
-            */
+        if self.bdos {
+            self.bdos_hook();
+        }
 
-            self.current_opcode = opcode.opcode;
+        if self.stop.is_some() {
+            return self.stop.take();
+        }
 
-            self.pc += 1;
+        self.record_trace();
+        let opcode = Opcode::new(self.ram.fetch_opcode(self.pc));
 
-            self.run_instruction(opcode);
-            self.instruction_count += 1;
-            
-            //println!("{:?}", self);
+        /*
+        if opcode.opcode == 0x76 {
+            println!("HALT at {:#06x}", self.pc);
+        }
+        */
+
+        self.current_opcode = opcode.opcode;
+
+        let pc = self.pc;
+        let sp = self.sp;
+        let cycles = self.cycles;
+
+        self.pc += 1;
+
+        self.run_instruction(opcode);
+        self.instruction_count += 1;
+        self.total_cycles += (self.cycles - cycles) as u64;
+        self.ram.cycle = self.total_cycles;
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(pc, self.current_opcode, (self.cycles - cycles) as u64, self.sp);
+        }
+
+        if let Some(ref mut call_graph) = self.call_graph {
+            call_graph.record(self.current_opcode, self.pc, sp, self.sp, (self.cycles - cycles) as u64);
         }
+        
+        //println!("{:?}", self);
+
+        if let Some(message) = self.ram.stop.take().or_else(|| self.io.stop.take()) {
+            println!("{}, stopping at {:#06x}", message, pc);
+            print!("{}", self.trace.backtrace());
+            self.stop = Some(StopReason::Violation(message));
+        }
+
+        self.stop.take()
+    }
+
+    fn record_trace(&mut self) {
+        let entry = TraceEntry {
+            pc: self.pc,
+            opcode: self.ram.peek_byte(self.pc),
+            operands: [self.ram.peek_byte(self.pc.wrapping_add(1)), self.ram.peek_byte(self.pc.wrapping_add(2))],
+
+            a: self.a,
+            f: self.f,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+        };
+
+        self.trace.record(entry);
     }
 }
 
 // Read/Write register methods and utility
 impl Cpu {
-    pub fn read_byte(&self, index: u8) -> u8 {
+    pub fn read_byte(&mut self, index: u8) -> u8 {
         match index {
             0 => self.b,
             1 => self.c,
@@ -168,7 +330,7 @@ impl Cpu {
     }
 
     pub fn read_stack(&self) -> u16 {
-        let value = u8_to_u16(self.ram.read_byte(self.sp + 1), self.ram.read_byte(self.sp));
+        let value = u8_to_u16(self.ram.peek_byte(self.sp + 1), self.ram.peek_byte(self.sp));
 
         value
     }
@@ -246,14 +408,14 @@ impl Cpu {
     }
 
     pub fn read_im_byte(&mut self) -> u8 {
-        let im = self.ram.read_byte(self.pc);
+        let im = self.ram.fetch_operand(self.pc);
         self.pc += 1;
 
         im
     }
 
     pub fn read_im_dword(&mut self) -> u16 {
-        let im = self.ram.read_dword(self.pc);
+        let im = u8_to_u16(self.ram.fetch_operand(self.pc), self.ram.fetch_operand(self.pc + 1));
         self.pc += 2;
 
         im
@@ -331,15 +493,21 @@ impl Cpu {
         let elapsed_nanos = elapsed.as_secs() + nanos;
         let needed: u64 = 1000000000/120;
 
-        if elapsed_nanos < needed {
+        if elapsed_nanos < needed && self.window.is_some() {
             let sleep_period = (needed - elapsed_nanos) / 1_000_000;
             let sleep_duration = time::Duration::from_millis(sleep_period);
 
             thread::sleep(sleep_duration);
         }
 
-        if self.cycles > 16667 {
-            self.cycles -= 16667;
+        if self.cycles > CYCLES_PER_INTERRUPT {
+            self.cycles -= CYCLES_PER_INTERRUPT;
+            self.half_frames += 1;
+            self.check_watchdog();
+
+            if self.half_frames.is_multiple_of(2) && self.dump_at_frame == Some(self.half_frames / 2) {
+                self.write_memory_dump();
+            }
 
             if self.read_flag(FLAG_INT) {
                 self.interrupt();
@@ -361,14 +529,80 @@ impl Cpu {
             address = INT_END;
             self.handle_input();
             self.vblank();
+
+            if let Some(ref mut memory_viewer) = self.memory_viewer {
+                memory_viewer.update(&mut self.ram);
+            }
+
+            if let Some(Err(e)) = self.sound_log.as_mut().map(|sound_log| sound_log.drain()) {
+                self.stop = Some(StopReason::Error(format!("Unable to write sound log: {}", e)));
+            }
+
+            let total_cycles = self.total_cycles;
+
+            if let Some(Err(e)) = self.audio.as_mut().map(|audio| audio.update(total_cycles)) {
+                self.stop = Some(StopReason::Error(format!("Unable to write audio: {}", e)));
+            }
         }
 
         self.push_stack(pc);
         self.pc = address;
 
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.enter_interrupt(address, self.sp);
+        }
+
+        if let Some(ref mut call_graph) = self.call_graph {
+            call_graph.interrupt(address, self.sp);
+        }
+
         self.last_interrupt = address;
     }
 
+    // Counted on every half frame rather than in the interrupt handler, so a
+    // game that hangs with interrupts disabled is caught too.
+    fn check_watchdog(&mut self) {
+        // The watchdog is released before acting, a reset resets it as well.
+        let (action, report) = match self.watchdog {
+            Some(ref watchdog) => {
+                let mut watchdog = watchdog.borrow_mut();
+
+                match watchdog.advance(CYCLES_PER_INTERRUPT as u64) {
+                    Some(action) => (action, watchdog.report(self.total_cycles)),
+                    None => return,
+                }
+            },
+            None => return,
+        };
+
+        match action {
+            WatchdogAction::Reset => {
+                println!("{}, resetting", report);
+                self.reset();
+            },
+            WatchdogAction::Log => println!("{}", report),
+            WatchdogAction::Stop => {
+                println!("{}, stopping at {:#06x}", report, self.pc);
+                print!("{}", self.trace.backtrace());
+                self.stop = Some(StopReason::Watchdog);
+            },
+        }
+    }
+
+    // Like the reset line: PC back to 0, interrupts off and the devices back
+    // to their power-on state. Memory and registers are kept, and the total
+    // cycle count keeps running so audio and logs stay in order.
+    pub fn reset(&mut self) {
+        self.pc = 0x0000;
+        self.f &= !FLAG_INT;
+        self.interrupt_in_progress = false;
+        self.cycles = 0;
+        self.last_interrupt = INT_MID;
+
+        self.io.reset(self.total_cycles);
+        self.ram.reset(self.total_cycles);
+    }
+
     pub fn dump_flags(&mut self) {
         println!("Z: {:?} AC: {:?} C: {:?} P: {:?} S: {:?} I: {:?}", 
             self.read_flag(FLAG_Z),
@@ -379,6 +613,35 @@ impl Cpu {
             self.read_flag(FLAG_INT)
         );
     }
+
+    pub fn crash_dump(&mut self) {
+        let backtrace = self.trace.backtrace();
+
+        println!("Last {} instructions:", self.trace.entries().len());
+        print!("{}", backtrace);
+
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let dir = format!("crash-{}", secs);
+
+        match self.write_crash_dump(&dir, &backtrace) {
+            Ok(()) => println!("Crash dump written to {}", dir),
+            Err(e) => println!("Unable to write crash dump to {}: {}", dir, e),
+        }
+    }
+
+    fn write_crash_dump(&self, dir: &str, backtrace: &str) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let mut f = File::create(format!("{}/backtrace.txt", dir))?;
+        f.write_all(backtrace.as_bytes())?;
+        writeln!(f, "{:?}", self)?;
+        writeln!(f, "Instruction Count: {:?}", self.instruction_count)?;
+
+        self.save_state(&format!("{}/state.bin", dir))?;
+
+        let mut f = File::create(format!("{}/ram.bin", dir))?;
+        f.write_all(&self.ram.bytes)
+    }
 }
 
 
@@ -389,22 +652,40 @@ impl Cpu {
     }
 
     fn vblank(&mut self) {
+        match self.window {
+            Some(ref window) if !window.is_open() => {
+                self.stop = Some(StopReason::WindowClosed);
+                return;
+            },
+            Some(_) => (),
+            None => return,
+        }
+
         let mut framebuffer: Vec<u32> = Vec::new();
         let mut framebuffer_new: Vec<u32> = Vec::new();
+        let color_ram = self.color_ram.as_ref().map(|color_ram| color_ram.borrow());
 
         for (i, byte) in self.get_vram().iter().enumerate() {
+            let ink = match color_ram {
+                Some(ref color_ram) => colorram::palette(color_ram.color(i)),
+                None => WHITE,
+            };
+
             for shift in 0..8 {
-                let pixel = if (byte & (1 << shift)) == 0 {
-                    [0, 0, 0, 255]
-                } else {
-                    [255, 255, 255, 255]
-                };
-            
-                let mut buff = &pixel[..];
-                let num = buff.read_u32::<BigEndian>().unwrap();
+                framebuffer.push(if (byte & (1 << shift)) == 0 { BLACK } else { ink });
+            }
+        }
+
+        if self.flipped() {
+            framebuffer.reverse();
+        }
 
-                framebuffer.push(num);
+        if self.orientation == Orientation::Rot0 {
+            if let Some(ref mut window) = self.window {
+                window.update_with_buffer(&self.tint.apply(&framebuffer)).unwrap();
             }
+
+            return;
         }
 
         for y in (0..HEIGHT).rev() {
@@ -413,43 +694,61 @@ impl Cpu {
             }
         }
 
-        self.window.update_with_buffer(&framebuffer_new).unwrap();
+        // The overlay is stuck on the monitor, so it goes on after the rotation.
+        if let Some(ref mut window) = self.window {
+            window.update_with_buffer(&self.tint.apply(&framebuffer_new)).unwrap();
+        }
+    }
+
+    // Cocktail tables turn the picture around during player 2's turn.
+    fn flipped(&self) -> bool {
+        let cocktail = self.panel.as_ref().is_some_and(|panel| panel.borrow().dips.cocktail);
+        cocktail && self.sound.as_ref().is_some_and(|sound| sound.borrow().flip())
+    }
+
+    pub fn set_overlay(&mut self, overlay: Option<&Overlay>) {
+        let (width, height) = match self.orientation {
+            Orientation::Rot0 => (HEIGHT, WIDTH),
+            Orientation::Rot270 => (WIDTH, HEIGHT),
+        };
+
+        self.tint = match overlay {
+            Some(overlay) => Tint::new(overlay, width, height),
+            None => Tint::monochrome(width, height),
+        };
     }
 
     fn handle_input(&mut self) {
-        if !self.window.is_open() {
-            return ();
-        }
+        let window = match self.window {
+            Some(ref window) if window.is_open() => window,
+            _ => return,
+        };
 
-        let mut input_received = false;
+        let held = self.bindings.held(window);
+        // One dump per press.
+        let dump_key = window.is_key_down(DUMP_KEY);
 
-        if self.window.is_key_down(Key::Left) {
-            self.inp1 |= (1 << 5);
-            input_received = true;
+        if let Some(ref panel) = self.panel {
+            panel.borrow_mut().update(&held);
         }
 
-        if self.window.is_key_down(Key::Right) {
-            self.inp1 |= (1 << 6);
-            input_received = true;
-        }
 
-        if self.window.is_key_down(Key::C) {
-            self.inp1 |= (1 << 0);
-            input_received = true;
+        if dump_key && !self.dump_key_held {
+            self.write_memory_dump();
         }
 
-        if self.window.is_key_down(Key::X) {
-            self.inp1 |= (1 << 2);
-            input_received = true;
-        }
+        self.dump_key_held = dump_key;
+    }
 
-        if self.window.is_key_down(Key::Z) {
-            self.inp1 |= (1 << 4);
-            input_received = true;
-        }
+    pub fn write_memory_dump(&mut self) {
+        let dump = match self.memory_dump {
+            Some(ref dump) => dump,
+            None => return,
+        };
 
-        if !input_received {
-            self.inp1 = 0x0;
+        match dump.write(&self.ram) {
+            Ok(()) => println!("Dumped {:04x}-{:04x} to {} at frame {}", dump.start, dump.end, dump.path, self.half_frames / 2),
+            Err(e) => self.stop = Some(StopReason::Error(format!("Unable to write {}: {}", dump.path, e))),
         }
     }
 }
@@ -543,6 +842,7 @@ impl Cpu {
             0x17                                                    => { ral(self); self.cycles += 4; },
             0xF9                                                    => { sphl(self); self.cycles += 5; },
             0xF3                                                    => { di(self); self.cycles += 4; },
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF   => { rst(self); self.cycles += 11; },
 
             _ => {
                 println!("Unknown opcode: {:?}", opcode);
@@ -572,4 +872,48 @@ impl fmt::Debug for Cpu {
             self.pc, Opcode::new(self.current_opcode).opcode, self.cycles, self.sp, self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.f
         )*/
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memmap::{MemoryMap, RegionKind, Violation};
+
+    fn cpu(program: &[u8]) -> Cpu {
+        let mut ram = Sram::new();
+        ram.load_bytes(program, 0x0000);
+        ram.map = MemoryMap::invaders();
+
+        let mut cpu = Cpu::headless(ram);
+        cpu.max_instructions = Some(100);
+        cpu
+    }
+
+    #[test]
+    fn stop_policies_end_the_run_without_a_fault() {
+        // MVI A,0x55; STA 0x0010
+        let mut cpu = cpu(&[0x3e, 0x55, 0x32, 0x10, 0x00]);
+        cpu.ram.map.set_violation(RegionKind::Rom, Violation::Stop);
+
+        assert_eq!(cpu.run(), StopReason::Violation(String::from("Write of 0x55 to ROM at 0x0010")));
+        assert_eq!(cpu.pc, 0x0005);
+        assert_eq!(cpu.ram.bytes[0x0010], 0x00);
+    }
+
+    #[test]
+    fn unclaimed_ports_can_stop_the_run() {
+        // IN 0x01
+        let mut cpu = cpu(&[0xdb, 0x01]);
+        cpu.io.unclaimed_policy = Violation::Stop;
+
+        assert_eq!(cpu.run(), StopReason::Violation(String::from("Unclaimed In port 0x01")));
+    }
+
+    #[test]
+    fn limits_are_not_errors() {
+        let mut cpu = cpu(&[]);
+        let reason = cpu.run();
+
+        assert_eq!(reason, StopReason::Limit(String::from("100 instructions")));
+        assert!(!reason.is_error());
+    }
+}
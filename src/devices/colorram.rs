@@ -0,0 +1,107 @@
+use std::io::{self, Read, Write};
+
+use memmap::MemoryDevice;
+
+// Where the Taito color boards map their color RAM.
+pub const COLOR_RAM_START: u16 = 0xc000;
+pub const COLOR_RAM_END: u16 = 0xdfff;
+
+// Video RAM starts 0x400 bytes into the RAM at 0x2000, color RAM is indexed
+// the same way.
+const VRAM_OFFSET: usize = 0x400;
+// A5-A7 pick the line within a cell and are not decoded, so all eight lines
+// of a cell share one byte.
+const DECODED: usize = 0x1f1f;
+
+// One color for every 8x8 pixel cell of the screen, in the low 3 bits of the
+// byte mirroring the cell's first video RAM byte.
+pub struct ColorRam {
+    pub bytes: Vec<u8>,
+}
+
+impl ColorRam {
+    pub fn new() -> ColorRam {
+        ColorRam {
+            bytes: vec![0; (COLOR_RAM_END - COLOR_RAM_START) as usize + 1],
+        }
+    }
+
+    // Color of the cell holding the video RAM byte at `offset` from 0x2400.
+    pub fn color(&self, offset: usize) -> u8 {
+        self.bytes[(offset + VRAM_OFFSET) & DECODED] & 0x07
+    }
+}
+
+impl MemoryDevice for ColorRam {
+    fn read(&mut self, offset: u16, _cycle: u64) -> u8 {
+        self.peek(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8, _cycle: u64) {
+        self.bytes[offset as usize & DECODED] = value;
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.bytes[offset as usize & DECODED]
+    }
+
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.bytes)
+    }
+
+    fn load(&mut self, input: &mut dyn Read, _cycle: u64) -> io::Result<()> {
+        input.read_exact(&mut self.bytes)
+    }
+}
+
+// Bit 0 drives red, bit 1 blue and bit 2 green.
+pub fn palette(color: u8) -> u32 {
+    let mut rgb = 0;
+
+    if color & 0x01 != 0 {
+        rgb |= 0xff0000;
+    }
+
+    if color & 0x02 != 0 {
+        rgb |= 0x0000ff;
+    }
+
+    if color & 0x04 != 0 {
+        rgb |= 0x00ff00;
+    }
+
+    rgb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_are_eight_lines_high() {
+        let mut ram = ColorRam::new();
+        ram.write(0x0405, 0x04, 0);
+        ram.write(0x0505, 0xf9, 0);
+
+        // Line 0 to 7 of column 5 share a color, line 8 starts the next cell.
+        assert_eq!(ram.color(5), 0x04);
+        assert_eq!(ram.color(7 * 32 + 5), 0x04);
+        assert_eq!(ram.color(8 * 32 + 5), 0x01);
+        assert_eq!(ram.color(6), 0x00);
+
+        // Writing through line 1 of the cell lands on the same byte.
+        ram.write(0x0425, 0x02, 0);
+        assert_eq!(ram.color(5), 0x02);
+        assert_eq!(ram.color(3 * 32 + 5), 0x02);
+        assert_eq!(ram.peek(0x04e5), 0x02);
+        assert_eq!(ram.read(0x0405, 0), 0x02);
+    }
+
+    #[test]
+    fn palette_bits() {
+        assert_eq!(palette(0), 0x000000);
+        assert_eq!(palette(1), 0xff0000);
+        assert_eq!(palette(4), 0x00ff00);
+        assert_eq!(palette(7), 0xffffff);
+    }
+}
@@ -0,0 +1,136 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DipLayout {
+    Invaders,
+    // Space Invaders Part II and the other color boards: 3 or 4 ships on bit 0
+    // and no extra ship switch, bit 3 is the high score preset mode instead.
+    Color,
+}
+
+// The switch bank read through bit 0, 1, 3 and 7 of IN 2 on Space Invaders,
+// plus the tilt switch on bit 2.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InvadersDips {
+    pub layout: DipLayout,
+    // 3 to 6.
+    pub ships: u8,
+    // 1000 or 1500 points.
+    pub extra_ship_at: u16,
+    pub coin_info: bool,
+    pub tilt: bool,
+    // Cabinet wiring rather than a switch in the bank. A cocktail table gives
+    // player 2 their own controls and lets the game flip the picture.
+    pub cocktail: bool,
+}
+
+impl InvadersDips {
+    pub fn new() -> InvadersDips {
+        InvadersDips::with_layout(DipLayout::Invaders)
+    }
+
+    pub fn with_layout(layout: DipLayout) -> InvadersDips {
+        InvadersDips {
+            layout,
+            ships: 3,
+            extra_ship_at: 1500,
+            coin_info: true,
+            tilt: false,
+            cocktail: false,
+        }
+    }
+
+    pub fn set_ships(&mut self, ships: u8) -> Result<(), String> {
+        let most = match self.layout {
+            DipLayout::Invaders => 6,
+            DipLayout::Color => 4,
+        };
+
+        if !(3..=most).contains(&ships) {
+            return Err(format!("Ships per game must be 3 to {}, not {}", most, ships));
+        }
+
+        self.ships = ships;
+        Ok(())
+    }
+
+    pub fn set_extra_ship_at(&mut self, score: u16) -> Result<(), String> {
+        if score != 1000 && score != 1500 {
+            return Err(format!("Extra ship must be at 1000 or 1500, not {}", score));
+        }
+
+        self.extra_ship_at = score;
+        Ok(())
+    }
+
+    // The extra ship setting is kept but has no switch on the color boards.
+    pub fn bits(&self) -> u8 {
+        let mut bits = self.ships - 3;
+
+        if self.tilt {
+            bits |= 1 << 2;
+        }
+
+        if self.extra_ship_at == 1000 && self.layout == DipLayout::Invaders {
+            bits |= 1 << 3;
+        }
+
+        // The switch is active low.
+        if !self.coin_info {
+            bits |= 1 << 7;
+        }
+
+        bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_read_as_zero() {
+        assert_eq!(InvadersDips::new().bits(), 0x00);
+        assert_eq!(InvadersDips::with_layout(DipLayout::Color).bits(), 0x00);
+    }
+
+    #[test]
+    fn ships_go_in_the_low_two_bits() {
+        let mut dips = InvadersDips::new();
+
+        for ships in 3..=6 {
+            dips.set_ships(ships).unwrap();
+            assert_eq!(dips.bits(), ships - 3);
+        }
+
+        assert!(dips.set_ships(2).is_err());
+        assert!(dips.set_ships(7).is_err());
+    }
+
+    #[test]
+    fn switches_have_their_own_bits() {
+        let mut dips = InvadersDips::new();
+        dips.tilt = true;
+        assert_eq!(dips.bits(), 0x04);
+
+        dips.tilt = false;
+        dips.set_extra_ship_at(1000).unwrap();
+        assert_eq!(dips.bits(), 0x08);
+
+        dips.set_extra_ship_at(1500).unwrap();
+        dips.coin_info = false;
+        assert_eq!(dips.bits(), 0x80);
+
+        assert!(dips.set_extra_ship_at(2000).is_err());
+    }
+
+    #[test]
+    fn color_boards_have_no_extra_ship_switch() {
+        let mut dips = InvadersDips::with_layout(DipLayout::Color);
+        dips.set_extra_ship_at(1000).unwrap();
+        assert_eq!(dips.bits(), 0x00);
+
+        dips.set_ships(4).unwrap();
+        assert_eq!(dips.bits(), 0x01);
+        assert!(dips.set_ships(5).is_err());
+        assert_eq!(dips.ships, 4);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod shifter;
+pub mod sound;
+pub mod watchdog;
+pub mod dips;
+pub mod panel;
+pub mod colorram;
@@ -0,0 +1,229 @@
+use std::io::{self, Read, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use devices::dips::InvadersDips;
+use io::IoDevice;
+
+// Frames the coin switch stays closed after a coin is dropped, long enough for
+// the game's debounce.
+pub const COIN_PULSE_FRAMES: u8 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Button {
+    Coin,
+    P1Start,
+    P2Start,
+    P1Fire,
+    P1Left,
+    P1Right,
+    P2Fire,
+    P2Left,
+    P2Right,
+    Tilt,
+}
+
+impl Button {
+    pub fn parse(name: &str) -> Option<Button> {
+        BUTTONS.iter().find(|&&(_, button_name)| button_name == name).map(|&(button, _)| button)
+    }
+}
+
+// Names used in the key binding config.
+pub const BUTTONS: [(Button, &str); 10] = [
+    (Button::Coin, "coin"),
+    (Button::P1Start, "start1"),
+    (Button::P2Start, "start2"),
+    (Button::P1Fire, "p1_fire"),
+    (Button::P1Left, "p1_left"),
+    (Button::P1Right, "p1_right"),
+    (Button::P2Fire, "p2_fire"),
+    (Button::P2Left, "p2_left"),
+    (Button::P2Right, "p2_right"),
+    (Button::Tilt, "tilt"),
+];
+
+// (port, bit) for every button.
+const LAYOUT: [(Button, u8, u8); 10] = [
+    (Button::Coin, 1, 0),
+    (Button::P2Start, 1, 1),
+    (Button::P1Start, 1, 2),
+    (Button::P1Fire, 1, 4),
+    (Button::P1Left, 1, 5),
+    (Button::P1Right, 1, 6),
+    (Button::Tilt, 2, 2),
+    (Button::P2Fire, 2, 4),
+    (Button::P2Left, 2, 5),
+    (Button::P2Right, 2, 6),
+];
+
+// Bit 3 of IN 1 is tied high on the board.
+const PORT1_ALWAYS_SET: u8 = 1 << 3;
+
+// Player controls on IN 1 and IN 2 of the Midway boards, sharing IN 2 with
+// the DIP switches. `ports` are the port numbers the board puts them on.
+pub struct ControlPanel {
+    pub dips: InvadersDips,
+    pub ports: [u8; 2],
+
+    held: Vec<Button>,
+    coin_frames: u8,
+    coin_held: bool,
+}
+
+impl ControlPanel {
+    pub fn new(ports: [u8; 2]) -> ControlPanel {
+        ControlPanel {
+            dips: InvadersDips::new(),
+            ports,
+
+            held: Vec::new(),
+            coin_frames: 0,
+            coin_held: false,
+        }
+    }
+
+    // Called once a frame with every button that is down. Holding the coin
+    // button only drops one coin, it has to be released for the next.
+    pub fn update(&mut self, held: &[Button]) {
+        let coin = held.contains(&Button::Coin);
+
+        if coin && !self.coin_held {
+            self.coin_frames = COIN_PULSE_FRAMES;
+        } else if self.coin_frames > 0 {
+            self.coin_frames -= 1;
+        }
+
+        self.coin_held = coin;
+        self.held = held.iter().cloned().filter(|&button| button != Button::Coin).collect();
+    }
+
+    pub fn port(&self, port: u8) -> u8 {
+        let mut value = match port {
+            1 => PORT1_ALWAYS_SET,
+            2 => self.dips.bits(),
+            _ => 0x00,
+        };
+
+        for &(button, button_port, bit) in LAYOUT.iter() {
+            if button_port == port && self.is_down(button) {
+                value |= 1 << bit;
+            }
+        }
+
+        value
+    }
+
+    // An upright cabinet has one set of controls, wired to both players' inputs.
+    fn is_down(&self, button: Button) -> bool {
+        let shared = match button {
+            Button::P2Fire => Some(Button::P1Fire),
+            Button::P2Left => Some(Button::P1Left),
+            Button::P2Right => Some(Button::P1Right),
+            _ => None,
+        };
+
+        match (button, shared) {
+            (Button::Coin, _) => self.coin_frames > 0,
+            (_, Some(p1)) if !self.dips.cocktail => self.held.contains(&button) || self.held.contains(&p1),
+            _ => self.held.contains(&button),
+        }
+    }
+}
+
+impl IoDevice for ControlPanel {
+    fn input(&mut self, port: u8, _cycle: u64) -> u8 {
+        if port == self.ports[0] {
+            self.port(1)
+        } else if port == self.ports[1] {
+            self.port(2)
+        } else {
+            0x00
+        }
+    }
+
+    fn output(&mut self, _port: u8, _value: u8, _cycle: u64) {
+    }
+
+    // A coin still being counted in is lost.
+    fn reset(&mut self, _cycle: u64) {
+        self.coin_frames = 0;
+    }
+
+    // Only the coin pulse, the buttons come from the keyboard every frame.
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u8(self.coin_frames)
+    }
+
+    fn load(&mut self, input: &mut dyn Read, _cycle: u64) -> io::Result<()> {
+        self.coin_frames = input.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_panel() {
+        let panel = ControlPanel::new([0x01, 0x02]);
+
+        assert_eq!(panel.port(1), 0x08);
+        assert_eq!(panel.port(2), 0x00);
+    }
+
+    #[test]
+    fn player_controls() {
+        let mut panel = ControlPanel::new([0x01, 0x02]);
+
+        panel.update(&[Button::P1Left, Button::P1Fire, Button::P2Start]);
+        assert_eq!(panel.port(1), 0x08 | 0x20 | 0x10 | 0x02);
+
+        panel.update(&[Button::P2Right, Button::P2Fire, Button::Tilt]);
+        assert_eq!(panel.port(1), 0x08);
+        assert_eq!(panel.port(2), 0x40 | 0x10 | 0x04);
+    }
+
+    #[test]
+    fn upright_shares_player1_controls() {
+        let mut panel = ControlPanel::new([0x01, 0x02]);
+
+        panel.update(&[Button::P1Fire, Button::P1Right]);
+        assert_eq!(panel.port(2), 0x10 | 0x40);
+
+        panel.dips.cocktail = true;
+        assert_eq!(panel.port(2), 0x00);
+
+        panel.update(&[Button::P2Left]);
+        assert_eq!(panel.port(1), 0x08);
+        assert_eq!(panel.port(2), 0x20);
+    }
+
+    #[test]
+    fn controls_share_port2_with_the_dips() {
+        let mut panel = ControlPanel::new([0x01, 0x02]);
+        panel.dips.set_ships(6).unwrap();
+        panel.dips.coin_info = false;
+
+        panel.update(&[Button::P2Left]);
+        assert_eq!(panel.port(2), 0x80 | 0x20 | 0x03);
+    }
+
+    #[test]
+    fn coin_is_a_single_pulse() {
+        let mut panel = ControlPanel::new([0x01, 0x02]);
+        let mut pulse = Vec::new();
+
+        for _ in 0..10 {
+            panel.update(&[Button::Coin]);
+            pulse.push(panel.port(1) & 0x01);
+        }
+
+        assert_eq!(pulse.iter().filter(|&&bit| bit != 0).count(), COIN_PULSE_FRAMES as usize);
+        assert_eq!(pulse[0], 1);
+
+        panel.update(&[]);
+        panel.update(&[Button::Coin]);
+        assert_eq!(panel.port(1) & 0x01, 1);
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use io::IoDevice;
+
+// The MB14241 style shift register found on most Midway 8080 boards. Writing
+// the data port shifts a byte into the top of a 16 bit register, the offset
+// port selects which 8 bits the result port returns.
+pub struct ShiftRegister {
+    pub offset_port: u8,
+    pub data_port: u8,
+    pub result_port: u8,
+
+    pub value: u16,
+    pub offset: u8,
+}
+
+impl ShiftRegister {
+    pub fn new(offset_port: u8, data_port: u8, result_port: u8) -> ShiftRegister {
+        ShiftRegister {
+            offset_port,
+            data_port,
+            result_port,
+
+            value: 0x0000,
+            offset: 0,
+        }
+    }
+
+    pub fn result(&self) -> u8 {
+        (self.value >> (8 - self.offset)) as u8
+    }
+}
+
+impl IoDevice for ShiftRegister {
+    fn input(&mut self, port: u8, _cycle: u64) -> u8 {
+        if port == self.result_port {
+            self.result()
+        } else {
+            0x00
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8, _cycle: u64) {
+        if port == self.offset_port {
+            self.offset = value & 0x07;
+        } else if port == self.data_port {
+            self.value = ((value as u16) << 8) | (self.value >> 8);
+        }
+    }
+
+    fn reset(&mut self, _cycle: u64) {
+        self.value = 0;
+        self.offset = 0;
+    }
+
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u16::<LittleEndian>(self.value)?;
+        out.write_u8(self.offset)
+    }
+
+    fn load(&mut self, input: &mut dyn Read, _cycle: u64) -> io::Result<()> {
+        self.value = input.read_u16::<LittleEndian>()?;
+        self.offset = input.read_u8()? & 0x07;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shifter() -> ShiftRegister {
+        ShiftRegister::new(0x02, 0x04, 0x03)
+    }
+
+    #[test]
+    fn data_shifts_into_the_top_byte() {
+        let mut shifter = shifter();
+
+        shifter.output(0x04, 0xab, 0);
+        assert_eq!(shifter.value, 0xab00);
+
+        shifter.output(0x04, 0xcd, 0);
+        assert_eq!(shifter.value, 0xcdab);
+    }
+
+    #[test]
+    fn offset_zero_returns_the_top_byte() {
+        let mut shifter = shifter();
+
+        shifter.output(0x04, 0x34, 0);
+        shifter.output(0x04, 0x12, 0);
+
+        assert_eq!(shifter.input(0x03, 0), 0x12);
+    }
+
+    #[test]
+    fn offset_selects_the_result_window() {
+        let mut shifter = shifter();
+
+        shifter.output(0x04, 0x0f, 0);
+        shifter.output(0x04, 0xf0, 0);
+
+        let expected = [0xf0, 0xe0, 0xc0, 0x80, 0x00, 0x01, 0x03, 0x07];
+
+        for (offset, &result) in expected.iter().enumerate() {
+            shifter.output(0x02, offset as u8, 0);
+            assert_eq!(shifter.input(0x03, 0), result, "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn offset_uses_only_the_low_three_bits() {
+        let mut shifter = shifter();
+
+        shifter.output(0x04, 0x00, 0);
+        shifter.output(0x04, 0xff, 0);
+        shifter.output(0x02, 0xfb, 0);
+
+        assert_eq!(shifter.offset, 3);
+        assert_eq!(shifter.input(0x03, 0), 0xf8);
+    }
+
+    #[test]
+    fn any_offset_byte_is_safe() {
+        let mut shifter = shifter();
+        shifter.output(0x04, 0xff, 0);
+
+        for value in 0..=255 {
+            shifter.output(0x02, value, 0);
+            assert_eq!(shifter.input(0x03, 0), 0xffu8 << (value & 0x07));
+        }
+    }
+
+    #[test]
+    fn custom_ports() {
+        let mut shifter = ShiftRegister::new(0x01, 0x02, 0x03);
+
+        shifter.output(0x02, 0x80, 0);
+        shifter.output(0x02, 0x01, 0);
+        shifter.output(0x01, 0x01, 0);
+
+        assert_eq!(shifter.input(0x03, 0), 0x03);
+
+        // Other ports do not disturb the register.
+        shifter.output(0x04, 0xff, 0);
+        assert_eq!(shifter.value, 0x0180);
+        assert_eq!(shifter.input(0x04, 0), 0x00);
+    }
+}
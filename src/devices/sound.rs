@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::{self, Read, Write, BufWriter};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use io::IoDevice;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderDeath,
+    ExtraLife,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SoundEventKind {
+    Start(Sound),
+    Stop(Sound),
+    Amplifier(bool),
+    Flip(bool),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SoundEvent {
+    pub cycle: u64,
+    pub kind: SoundEventKind,
+}
+
+// Bit assignments on the two sound ports.
+const PORT3_SOUNDS: [(u8, Sound); 5] = [
+    (0, Sound::Ufo),
+    (1, Sound::Shot),
+    (2, Sound::PlayerDeath),
+    (3, Sound::InvaderDeath),
+    (4, Sound::ExtraLife),
+];
+const PORT3_AMPLIFIER: u8 = 1 << 5;
+
+const PORT5_SOUNDS: [(u8, Sound); 5] = [
+    (0, Sound::Fleet1),
+    (1, Sound::Fleet2),
+    (2, Sound::Fleet3),
+    (3, Sound::Fleet4),
+    (4, Sound::UfoHit),
+];
+const PORT5_FLIP: u8 = 1 << 5;
+
+// Space Invaders sound latches on OUT 3 and OUT 5. Only changes are reported,
+// so a sound produces one start and one stop event however often the game
+// rewrites the port.
+pub struct InvadersSound {
+    pub port3: u8,
+    pub port5: u8,
+
+    subscribers: Vec<Sender<SoundEvent>>,
+}
+
+impl InvadersSound {
+    pub fn new() -> InvadersSound {
+        InvadersSound {
+            port3: 0x00,
+            port5: 0x00,
+
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<SoundEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn amplifier(&self) -> bool {
+        self.port3 & PORT3_AMPLIFIER != 0
+    }
+
+    pub fn flip(&self) -> bool {
+        self.port5 & PORT5_FLIP != 0
+    }
+
+    fn decode(&mut self, old: u8, new: u8, sounds: &[(u8, Sound)], cycle: u64) {
+        for &(bit, sound) in sounds {
+            let mask = 1 << bit;
+
+            if old & mask == new & mask {
+                continue;
+            }
+
+            let kind = if new & mask != 0 {
+                SoundEventKind::Start(sound)
+            } else {
+                SoundEventKind::Stop(sound)
+            };
+
+            self.send(SoundEvent { cycle, kind });
+        }
+    }
+
+    fn send(&mut self, event: SoundEvent) {
+        // Receivers that have gone away are dropped.
+        self.subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+impl IoDevice for InvadersSound {
+    fn input(&mut self, _port: u8, _cycle: u64) -> u8 {
+        0x00
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        match port {
+            0x03 => {
+                let old = self.port3;
+                self.port3 = value;
+                self.decode(old, value, &PORT3_SOUNDS, cycle);
+
+                if (old ^ value) & PORT3_AMPLIFIER != 0 {
+                    let enabled = self.amplifier();
+                    self.send(SoundEvent { cycle, kind: SoundEventKind::Amplifier(enabled) });
+                }
+            },
+            0x05 => {
+                let old = self.port5;
+                self.port5 = value;
+                self.decode(old, value, &PORT5_SOUNDS, cycle);
+
+                if (old ^ value) & PORT5_FLIP != 0 {
+                    let flipped = self.flip();
+                    self.send(SoundEvent { cycle, kind: SoundEventKind::Flip(flipped) });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // Clearing the latches stops whatever was playing and mutes the amplifier.
+    fn reset(&mut self, cycle: u64) {
+        self.output(0x03, 0x00, cycle);
+        self.output(0x05, 0x00, cycle);
+    }
+
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&[self.port3, self.port5])
+    }
+
+    // Written like the game would, so sounds playing now stop and the saved
+    // ones start again.
+    fn load(&mut self, input: &mut dyn Read, cycle: u64) -> io::Result<()> {
+        let mut ports = [0; 2];
+        input.read_exact(&mut ports)?;
+
+        self.output(0x03, ports[0], cycle);
+        self.output(0x05, ports[1], cycle);
+        Ok(())
+    }
+}
+
+// Writes every sound event to a text file, one line per event.
+pub struct SoundLog {
+    events: Receiver<SoundEvent>,
+    file: BufWriter<File>,
+}
+
+impl SoundLog {
+    pub fn new(sound: &mut InvadersSound, file_name: &str) -> io::Result<SoundLog> {
+        Ok(SoundLog {
+            events: sound.subscribe(),
+            file: BufWriter::new(File::create(file_name)?),
+        })
+    }
+
+    pub fn drain(&mut self) -> io::Result<()> {
+        for event in self.events.try_iter() {
+            writeln!(self.file, "{:>12} {:?}", event.cycle, event.kind)?;
+        }
+
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(events: &Receiver<SoundEvent>) -> Vec<SoundEventKind> {
+        events.try_iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn edges_start_and_stop_sounds() {
+        let mut sound = InvadersSound::new();
+        let events = sound.subscribe();
+
+        sound.output(0x03, 0x02, 100);
+        sound.output(0x03, 0x02, 200);
+        sound.output(0x03, 0x00, 300);
+
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(received, vec![
+            SoundEvent { cycle: 100, kind: SoundEventKind::Start(Sound::Shot) },
+            SoundEvent { cycle: 300, kind: SoundEventKind::Stop(Sound::Shot) },
+        ]);
+    }
+
+    #[test]
+    fn fleet_and_flip_on_port5() {
+        let mut sound = InvadersSound::new();
+        let events = sound.subscribe();
+
+        sound.output(0x05, 0x01, 0);
+        sound.output(0x05, 0x22, 0);
+
+        assert_eq!(kinds(&events), vec![
+            SoundEventKind::Start(Sound::Fleet1),
+            SoundEventKind::Stop(Sound::Fleet1),
+            SoundEventKind::Start(Sound::Fleet2),
+            SoundEventKind::Flip(true),
+        ]);
+        assert!(sound.flip());
+    }
+
+    #[test]
+    fn amplifier_enable() {
+        let mut sound = InvadersSound::new();
+        let events = sound.subscribe();
+
+        sound.output(0x03, 0x20, 0);
+        sound.output(0x03, 0x21, 0);
+
+        assert_eq!(kinds(&events), vec![
+            SoundEventKind::Amplifier(true),
+            SoundEventKind::Start(Sound::Ufo),
+        ]);
+        assert!(sound.amplifier());
+    }
+
+    #[test]
+    fn every_subscriber_sees_events() {
+        let mut sound = InvadersSound::new();
+        let first = sound.subscribe();
+        let second = sound.subscribe();
+
+        drop(first);
+        sound.output(0x05, 0x10, 0);
+
+        assert_eq!(kinds(&second), vec![SoundEventKind::Start(Sound::UfoHit)]);
+        assert_eq!(sound.subscribers.len(), 1);
+    }
+}
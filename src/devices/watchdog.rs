@@ -0,0 +1,153 @@
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use cpu::CYCLES_PER_INTERRUPT;
+use io::IoDevice;
+
+// The Midway boards reset the CPU after 255 frames without a write to port 6.
+pub const DEFAULT_TIMEOUT: u64 = 255 * 2 * CYCLES_PER_INTERRUPT as u64;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchdogAction {
+    Reset,
+    Log,
+    Stop,
+}
+
+impl WatchdogAction {
+    pub fn parse(name: &str) -> Option<WatchdogAction> {
+        match name {
+            "reset" => Some(WatchdogAction::Reset),
+            "log" => Some(WatchdogAction::Log),
+            "stop" => Some(WatchdogAction::Stop),
+            _ => None,
+        }
+    }
+}
+
+pub struct Watchdog {
+    pub action: WatchdogAction,
+    pub timeout: u64,
+
+    // Emulated cycles since the last write.
+    pub elapsed: u64,
+    pub last_kick: Option<u64>,
+    pub expired_count: u64,
+}
+
+impl Watchdog {
+    pub fn new(timeout: u64) -> Watchdog {
+        Watchdog {
+            action: WatchdogAction::Reset,
+            timeout,
+
+            elapsed: 0,
+            last_kick: None,
+            expired_count: 0,
+        }
+    }
+
+    // Counts emulated time and returns the action to take once the timeout is
+    // reached. The counter starts over, so a game that stays wedged expires again.
+    pub fn advance(&mut self, cycles: u64) -> Option<WatchdogAction> {
+        self.elapsed += cycles;
+
+        if self.elapsed < self.timeout {
+            return None;
+        }
+
+        self.elapsed = 0;
+        self.expired_count += 1;
+        Some(self.action)
+    }
+
+    pub fn report(&self, cycle: u64) -> String {
+        let last_kick = match self.last_kick {
+            Some(kick) => format!("at cycle {}", kick),
+            None => String::from("never"),
+        };
+
+        format!("Watchdog expired at cycle {} ({} cycles without a write to port 6, last written {})",
+            cycle, self.timeout, last_kick)
+    }
+}
+
+impl IoDevice for Watchdog {
+    fn input(&mut self, _port: u8, _cycle: u64) -> u8 {
+        0x00
+    }
+
+    fn output(&mut self, _port: u8, _value: u8, cycle: u64) {
+        self.elapsed = 0;
+        self.last_kick = Some(cycle);
+    }
+
+    fn reset(&mut self, _cycle: u64) {
+        self.elapsed = 0;
+    }
+
+    // The last kick is only for reports, it is kept if there was none.
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u64::<LittleEndian>(self.elapsed)?;
+        out.write_u64::<LittleEndian>(self.expired_count)?;
+        out.write_u64::<LittleEndian>(self.last_kick.unwrap_or(u64::MAX))
+    }
+
+    fn load(&mut self, input: &mut dyn Read, _cycle: u64) -> io::Result<()> {
+        self.elapsed = input.read_u64::<LittleEndian>()?;
+        self.expired_count = input.read_u64::<LittleEndian>()?;
+        self.last_kick = Some(input.read_u64::<LittleEndian>()?).filter(|&cycle| cycle != u64::MAX);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_after_the_timeout_and_starts_over() {
+        let mut watchdog = Watchdog::new(100);
+
+        assert_eq!(watchdog.advance(60), None);
+        assert_eq!(watchdog.advance(40), Some(WatchdogAction::Reset));
+        assert_eq!(watchdog.elapsed, 0);
+        assert_eq!(watchdog.advance(99), None);
+        assert_eq!(watchdog.advance(1), Some(WatchdogAction::Reset));
+        assert_eq!(watchdog.expired_count, 2);
+    }
+
+    #[test]
+    fn writes_to_port_6_hold_it_off() {
+        let mut watchdog = Watchdog::new(100);
+
+        for cycle in 1..10 {
+            assert_eq!(watchdog.advance(60), None);
+            watchdog.output(0x06, 0x00, cycle * 60);
+        }
+
+        assert_eq!(watchdog.expired_count, 0);
+        assert!(watchdog.report(1000).contains("last written at cycle 540"));
+    }
+
+    #[test]
+    fn returns_the_configured_action() {
+        for &(name, action) in [("reset", WatchdogAction::Reset), ("log", WatchdogAction::Log), ("stop", WatchdogAction::Stop)].iter() {
+            let mut watchdog = Watchdog::new(10);
+            watchdog.action = WatchdogAction::parse(name).unwrap();
+
+            assert_eq!(watchdog.advance(10), Some(action));
+        }
+
+        assert_eq!(WatchdogAction::parse("ignore"), None);
+    }
+
+    #[test]
+    fn reset_restarts_the_count() {
+        let mut watchdog = Watchdog::new(100);
+        watchdog.advance(90);
+        watchdog.reset(0);
+
+        assert_eq!(watchdog.advance(90), None);
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt;
+use std::path::Path;
+
+// Intel HEX and Motorola S-record images, limited to the 8080's 64K.
+
+const BYTES_PER_RECORD: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    IntelHex,
+    SRecord,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "ihex" => Some(Format::IntelHex),
+            "srec" => Some(Format::SRecord),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &str) -> Option<Format> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "hex" | "ihx" | "ihex" => Some(Format::IntelHex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(Format::SRecord),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Image {
+    // Data records in file order.
+    pub chunks: Vec<(u16, Vec<u8>)>,
+    pub start: Option<u16>,
+}
+
+impl Image {
+    fn add(&mut self, line: usize, address: u32, data: Vec<u8>) -> Result<(), ParseError> {
+        if address as usize + data.len() > 0x10000 {
+            return Err(error(line, format!("data at {:#x} does not fit in 64K", address)));
+        }
+
+        self.chunks.push((address as u16, data));
+        Ok(())
+    }
+}
+
+// Picks the format from the first record.
+pub fn parse(text: &str) -> Result<Image, ParseError> {
+    match text.trim_start().chars().next() {
+        Some(':') => parse_ihex(text),
+        Some('S') | Some('s') => parse_srec(text),
+        _ => Err(error(1, String::from("not an Intel HEX or S-record file"))),
+    }
+}
+
+pub fn parse_ihex(text: &str) -> Result<Image, ParseError> {
+    let mut image = Image::default();
+    let mut base: u32 = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if !line.starts_with(':') {
+            return Err(error(number, String::from("record does not start with ':'")));
+        }
+
+        let bytes = decode(&line[1..], number)?;
+
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error(number, String::from("record length does not match its byte count")));
+        }
+
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(error(number, format!("bad checksum {:02x}", bytes[bytes.len() - 1])));
+        }
+
+        let address = u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            0x00 => image.add(number, base + address, data.to_vec())?,
+            0x01 => return Ok(image),
+            0x02 if data.len() == 2 => base = (u32::from(data[0]) << 8 | u32::from(data[1])) << 4,
+            0x04 if data.len() == 2 => base = (u32::from(data[0]) << 8 | u32::from(data[1])) << 16,
+            0x03 if data.len() == 4 => image.start = Some(u16::from(data[2]) << 8 | u16::from(data[3])),
+            0x05 if data.len() == 4 => image.start = Some(u16::from(data[2]) << 8 | u16::from(data[3])),
+            kind => return Err(error(number, format!("unsupported record type {:02x}", kind))),
+        }
+    }
+
+    Err(error(text.lines().count(), String::from("missing end of file record")))
+}
+
+pub fn parse_srec(text: &str) -> Result<Image, ParseError> {
+    let mut image = Image::default();
+    let mut data_records = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.len() < 2 || !(line.starts_with('S') || line.starts_with('s')) {
+            return Err(error(number, String::from("record does not start with 'S'")));
+        }
+
+        let kind = line.as_bytes()[1];
+        let digits = line.get(2..).ok_or_else(|| error(number, String::from("invalid record type")))?;
+        let bytes = decode(digits, number)?;
+
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error(number, String::from("record length does not match its byte count")));
+        }
+
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xff {
+            return Err(error(number, format!("bad checksum {:02x}", bytes[bytes.len() - 1])));
+        }
+
+        let address_size = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(error(number, format!("unsupported record type S{}", kind as char))),
+        };
+
+        if bytes.len() < address_size + 2 {
+            return Err(error(number, String::from("record too short for its address")));
+        }
+
+        let address = bytes[1..=address_size].iter().fold(0u32, |address, &byte| address << 8 | u32::from(byte));
+        let data = &bytes[address_size + 1..bytes.len() - 1];
+
+        match kind {
+            b'1' | b'2' | b'3' => {
+                image.add(number, address, data.to_vec())?;
+                data_records += 1;
+            },
+            b'5' | b'6' if address != data_records => {
+                return Err(error(number, format!("record count {} but {} data records were read", address, data_records)));
+            },
+            b'7' | b'8' | b'9' => {
+                if address > 0xffff {
+                    return Err(error(number, format!("start address {:#x} does not fit in 64K", address)));
+                }
+
+                image.start = Some(address as u16);
+                return Ok(image);
+            },
+            _ => (),
+        }
+    }
+
+    Ok(image)
+}
+
+pub fn write(format: Format, address: u16, bytes: &[u8], name: &str) -> String {
+    match format {
+        Format::IntelHex => write_ihex(address, bytes),
+        Format::SRecord => write_srec(address, bytes, name),
+    }
+}
+
+pub fn write_ihex(address: u16, bytes: &[u8]) -> String {
+    let mut text = String::new();
+
+    for (index, data) in bytes.chunks(BYTES_PER_RECORD).enumerate() {
+        let record_address = address.wrapping_add((index * BYTES_PER_RECORD) as u16);
+        let mut record = vec![data.len() as u8, (record_address >> 8) as u8, record_address as u8, 0x00];
+        record.extend_from_slice(data);
+
+        let checksum = record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)).wrapping_neg();
+        record.push(checksum);
+
+        text.push(':');
+        text.push_str(&encode(&record));
+        text.push('\n');
+    }
+
+    text.push_str(":00000001FF\n");
+    text
+}
+
+// The S9 record has to be there, it gives the 8080's reset address rather than
+// the dump's start so loading a dump does not move the PC.
+pub fn write_srec(address: u16, bytes: &[u8], name: &str) -> String {
+    let mut text = srec_record(b'0', 0, name.as_bytes());
+    let records = bytes.chunks(BYTES_PER_RECORD).count();
+
+    for (index, data) in bytes.chunks(BYTES_PER_RECORD).enumerate() {
+        text.push_str(&srec_record(b'1', address.wrapping_add((index * BYTES_PER_RECORD) as u16), data));
+    }
+
+    text.push_str(&srec_record(b'5', records as u16, &[]));
+    text.push_str(&srec_record(b'9', 0x0000, &[]));
+    text
+}
+
+fn srec_record(kind: u8, address: u16, data: &[u8]) -> String {
+    let mut record = vec![(data.len() + 3) as u8, (address >> 8) as u8, address as u8];
+    record.extend_from_slice(data);
+
+    let checksum = !record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    record.push(checksum);
+
+    format!("S{}{}\n", kind as char, encode(&record))
+}
+
+fn decode(digits: &str, line: usize) -> Result<Vec<u8>, ParseError> {
+    (0..digits.len()).step_by(2).map(|index| {
+        digits.get(index..index + 2)
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(|| error(line, format!("invalid hex digits at column {}", index + 2)))
+    }).collect()
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn error(line: usize, message: String) -> ParseError {
+    ParseError { line, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_hex_round_trip() {
+        let bytes: Vec<u8> = (0..40).collect();
+        let image = parse(&write_ihex(0x1ff8, &bytes)).unwrap();
+
+        assert_eq!(image.chunks.len(), 3);
+        assert_eq!(image.chunks[1].0, 0x2008);
+        assert_eq!(image.chunks.iter().flat_map(|chunk| chunk.1.clone()).collect::<Vec<u8>>(), bytes);
+    }
+
+    #[test]
+    fn intel_hex_reports_bad_checksum_line() {
+        let text = ":0300300002337A1E\n:03003000023379FF\n:00000001FF\n";
+        let error = parse(text).unwrap_err();
+
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("checksum"));
+    }
+
+    #[test]
+    fn srecord_round_trip() {
+        let bytes: Vec<u8> = (0..20).map(|x| x * 3).collect();
+        let text = write_srec(0x0100, &bytes, "test");
+        let image = parse(&text).unwrap();
+
+        assert!(text.starts_with("S0"));
+        assert!(text.ends_with("S9030000FC\n"));
+        assert_eq!(image.start, Some(0x0000));
+        assert_eq!(image.chunks, vec![(0x0100, bytes[..16].to_vec()), (0x0110, bytes[16..].to_vec())]);
+    }
+
+    #[test]
+    fn srecord_checks_record_count() {
+        let text = "S1130000285F245F2212226A000424290008237C2A\nS5030002FA\nS9030000FC\n";
+        let error = parse(text).unwrap_err();
+
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("record count"));
+    }
+
+    #[test]
+    fn multibyte_record_types_are_errors() {
+        let error = parse("S\u{e9}030000FC\n").unwrap_err();
+
+        assert_eq!(error.line, 1);
+        assert!(error.message.contains("record type"), "{}", error.message);
+    }
+}
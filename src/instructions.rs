@@ -32,51 +32,17 @@ pub fn di(state: &mut Cpu) {
 //Input/Output
 pub fn inp(state: &mut Cpu) {
     let port = state.read_im_byte();
+    let cycle = state.total_cycles;
 
-    match port {
-        0x01 => {
-            let inp1 = state.inp1;
-            state.write_byte(REG_A, inp1);
-        },
-        0x02 => {
-            let inp2 = state.inp2;
-            state.write_byte(REG_A, inp2);
-        },
-        0x03 => {
-            let port4hi = state.port4hi as u16;
-            let port4lo = state.port4lo as u16;
-            let port2 = state.port2 as u16;
-
-            let result = (((port4hi << 8) | port4lo) << port2) >> 8;
-
-            state.write_byte(REG_A, result as u8);
-        },
-        _ => (),
-    }
+    let value = state.io.input(port, cycle);
+    state.write_byte(REG_A, value);
 }
 
 pub fn out(state: &mut Cpu) {
     let port = state.read_im_byte();
+    let (a, cycle) = (state.a, state.total_cycles);
 
-    match port {
-        0x02 => {
-            let _a = state.a;
-            state.port2 = 0; //Temp, should b a
-        },
-        0x03 => {
-            let port3o = state.port3o;
-
-            state.write_byte(REG_A, port3o);
-        },
-        0x04 => {
-            let port4hi = state.port4hi;
-            let a = state.a;
-
-            state.port4lo = port4hi;
-            state.port4hi = a;
-        },
-        _ => /*println!("Unimplemented port for OUT: {:#04x}", port)*/ (),
-    }
+    state.io.output(port, a, cycle);
 }
 
 
@@ -198,6 +164,15 @@ pub fn cz(state: &mut Cpu) {
     }
 }
 
+pub fn rst(state: &mut Cpu) {
+    let address = (state.current_opcode & 0x38) as u16;
+
+    let pc = state.pc;
+    state.push_stack(pc);
+
+    state.pc = address;
+}
+
 //Return instructions
 pub fn ret(state: &mut Cpu) {
     let address = state.pop_stack();
@@ -871,4 +846,29 @@ pub fn cmc(state: &mut Cpu) {
 pub fn sphl(state: &mut Cpu) {
     let value = state.read_dword(REG_HL);
     state.sp = value;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use ram::Sram;
+
+    #[test]
+    fn rst_pushes_pc_and_jumps_to_n_times_8() {
+        for n in 0..8u16 {
+            let opcode = 0xc7 | (n << 3) as u8;
+
+            let mut ram = Sram::new();
+            ram.load_bytes(&[opcode], 0x1234);
+
+            let mut cpu = Cpu::headless(ram);
+            cpu.pc = 0x1234;
+            cpu.sp = 0x2400;
+            cpu.step();
+
+            assert_eq!(cpu.pc, n * 8, "RST {}", n);
+            assert_eq!(cpu.sp, 0x23fe);
+            assert_eq!(cpu.ram.bytes[0x23fe..0x2400], [0x35, 0x12]);
+            assert_eq!(cpu.cycles, 11);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write, BufWriter};
+use std::rc::Rc;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use memmap::{self, Violation};
+
+// Hardware behind the IN and OUT instructions.
+pub trait IoDevice {
+    fn input(&mut self, port: u8, cycle: u64) -> u8;
+    fn output(&mut self, port: u8, value: u8, cycle: u64);
+    // Back to the power-on state when the machine is reset.
+    fn reset(&mut self, _cycle: u64) {}
+    // Whatever the device latches, for save states. Configuration such as the
+    // port numbers comes from the machine and is not saved.
+    fn save(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+    fn load(&mut self, _input: &mut dyn Read, _cycle: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Lets the machine keep a handle on a device that is also attached to the bus.
+impl<T: IoDevice> IoDevice for Rc<RefCell<T>> {
+    fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        self.borrow_mut().input(port, cycle)
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        self.borrow_mut().output(port, value, cycle)
+    }
+
+    fn reset(&mut self, cycle: u64) {
+        self.borrow_mut().reset(cycle)
+    }
+
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.borrow().save(out)
+    }
+
+    fn load(&mut self, input: &mut dyn Read, cycle: u64) -> io::Result<()> {
+        self.borrow_mut().load(input, cycle)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+pub struct IoBus {
+    devices: Vec<Box<dyn IoDevice>>,
+    inputs: Vec<Option<usize>>,
+    outputs: Vec<Option<usize>>,
+
+    pub unclaimed_policy: Violation,
+    pub unclaimed: BTreeMap<(Direction, u8), u64>,
+    // Set by an access that should stop the machine.
+    pub stop: Option<String>,
+
+    log: Option<BufWriter<File>>,
+}
+
+impl IoBus {
+    pub fn new() -> IoBus {
+        IoBus {
+            devices: Vec::new(),
+            inputs: vec![None; 256],
+            outputs: vec![None; 256],
+
+            unclaimed_policy: Violation::Log,
+            unclaimed: BTreeMap::new(),
+            stop: None,
+
+            log: None,
+        }
+    }
+
+    pub fn attach(&mut self, inputs: &[u8], outputs: &[u8], device: Box<dyn IoDevice>) {
+        let index = self.devices.len();
+        self.devices.push(device);
+
+        for &port in inputs {
+            self.inputs[port as usize] = Some(index);
+        }
+
+        for &port in outputs {
+            self.outputs[port as usize] = Some(index);
+        }
+    }
+
+    pub fn reset(&mut self, cycle: u64) {
+        for device in self.devices.iter_mut() {
+            device.reset(cycle);
+        }
+    }
+
+    // Device states in the order they were attached.
+    pub fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u8(self.devices.len() as u8)?;
+
+        for device in self.devices.iter() {
+            device.save(out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(&mut self, input: &mut dyn Read, cycle: u64) -> io::Result<()> {
+        if input.read_u8()? as usize != self.devices.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Save state is for a different set of I/O devices"));
+        }
+
+        for device in self.devices.iter_mut() {
+            device.load(input, cycle)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn open_log(&mut self, file_name: &str) -> io::Result<()> {
+        self.log = Some(BufWriter::new(File::create(file_name)?));
+        Ok(())
+    }
+
+    pub fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        let value = match self.inputs[port as usize] {
+            Some(index) => self.devices[index].input(port, cycle),
+            None => {
+                self.unclaimed_access(Direction::In, port);
+                0x00
+            },
+        };
+
+        self.log_access(Direction::In, port, value, cycle);
+        value
+    }
+
+    pub fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        match self.outputs[port as usize] {
+            Some(index) => self.devices[index].output(port, value, cycle),
+            None => self.unclaimed_access(Direction::Out, port),
+        }
+
+        self.log_access(Direction::Out, port, value, cycle);
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        for (&(direction, port), count) in self.unclaimed.iter() {
+            out.push_str(&format!("Unclaimed {:?} port {:#04x}: {} times\n", direction, port, count));
+        }
+
+        out
+    }
+
+    fn unclaimed_access(&mut self, direction: Direction, port: u8) {
+        let count = self.unclaimed.entry((direction, port)).or_insert(0);
+        *count += 1;
+
+        // Only the first access to each port is logged, games poll constantly.
+        let policy = match self.unclaimed_policy {
+            Violation::Log if *count > 1 => Violation::Ignore,
+            policy => policy,
+        };
+
+        if let Err(message) = memmap::violation(policy, format!("Unclaimed {:?} port {:#04x}", direction, port)) {
+            self.stop = Some(message);
+        }
+    }
+
+    fn log_access(&mut self, direction: Direction, port: u8, value: u8, cycle: u64) {
+        let result = match self.log {
+            Some(ref mut log) => writeln!(log, "{:>12} {:<3} {:#04x} {:#04x}", cycle, format!("{:?}", direction).to_uppercase(), port, value),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            self.log = None;
+            self.stop = Some(format!("Unable to write I/O log: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::panel::{Button, ControlPanel};
+    use devices::shifter::ShiftRegister;
+    use devices::sound::{InvadersSound, Sound, SoundEventKind};
+
+    #[test]
+    fn reset_returns_devices_to_power_on() {
+        let shifter = Rc::new(RefCell::new(ShiftRegister::new(0x02, 0x04, 0x03)));
+        let sound = Rc::new(RefCell::new(InvadersSound::new()));
+        let panel = Rc::new(RefCell::new(ControlPanel::new([0x01, 0x02])));
+        let events = sound.borrow_mut().subscribe();
+
+        let mut bus = IoBus::new();
+        bus.attach(&[0x03], &[0x02, 0x04], Box::new(shifter.clone()));
+        bus.attach(&[], &[0x03, 0x05], Box::new(sound.clone()));
+        bus.attach(&[0x01, 0x02], &[], Box::new(panel.clone()));
+
+        bus.output(0x04, 0xff, 0);
+        bus.output(0x02, 0x03, 0);
+        bus.output(0x03, 0x21, 10);
+        panel.borrow_mut().update(&[Button::Coin]);
+        assert_eq!(bus.input(0x01, 20) & 0x01, 0x01);
+
+        bus.reset(100);
+
+        assert_eq!(bus.input(0x03, 100), 0x00);
+        assert_eq!(shifter.borrow().offset, 0);
+        assert_eq!(bus.input(0x01, 100) & 0x01, 0x00);
+        assert!(!sound.borrow().amplifier());
+
+        let stops: Vec<SoundEventKind> = events.try_iter().filter(|event| event.cycle == 100).map(|event| event.kind).collect();
+        assert_eq!(stops, vec![SoundEventKind::Stop(Sound::Ufo), SoundEventKind::Amplifier(false)]);
+    }
+}
@@ -0,0 +1,65 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cpu::Cpu;
+use devices::colorram::{ColorRam, COLOR_RAM_END, COLOR_RAM_START};
+use devices::dips::{DipLayout, InvadersDips};
+use devices::panel::ControlPanel;
+use devices::shifter::ShiftRegister;
+use devices::sound::InvadersSound;
+use devices::watchdog::{Watchdog, DEFAULT_TIMEOUT};
+use manifest::{Manifest, Ports};
+
+// Attaches the I/O for the manifest's machine type.
+pub fn build(cpu: &mut Cpu, manifest: &Manifest) {
+    match manifest.machine.as_str() {
+        "midway" => midway(cpu, &manifest.ports),
+        "invaders" => invaders(cpu, &manifest.ports),
+        "invaders-color" => invaders_color(cpu, &manifest.ports),
+        other => panic!("Unknown machine {:?}", other),
+    }
+}
+
+// I/O shared by the Midway 8080 boards: the control panel, the shift register
+// and the watchdog.
+pub fn midway(cpu: &mut Cpu, ports: &Ports) {
+    let panel = Rc::new(RefCell::new(ControlPanel::new(ports.panel)));
+    cpu.io.attach(&ports.panel, &[], Box::new(panel.clone()));
+    cpu.panel = Some(panel);
+
+    attach_shifter(cpu, ShiftRegister::new(ports.shift_offset, ports.shift_data, ports.shift_result));
+
+    let watchdog = Rc::new(RefCell::new(Watchdog::new(DEFAULT_TIMEOUT)));
+    cpu.io.attach(&[], &[ports.watchdog], Box::new(watchdog.clone()));
+    cpu.watchdog = Some(watchdog);
+}
+
+pub fn attach_shifter(cpu: &mut Cpu, shifter: ShiftRegister) {
+    let (inputs, outputs) = ([shifter.result_port], [shifter.offset_port, shifter.data_port]);
+    cpu.io.attach(&inputs, &outputs, Box::new(shifter));
+}
+
+pub fn invaders(cpu: &mut Cpu, ports: &Ports) {
+    midway(cpu, ports);
+
+    let sound = Rc::new(RefCell::new(InvadersSound::new()));
+    cpu.io.attach(&[], &[0x03, 0x05], Box::new(sound.clone()));
+    cpu.sound = Some(sound);
+}
+
+// Space Invaders Part II and the other Taito color boards: the Invaders I/O
+// with their own DIP switches, plus color RAM. The port numbers and the
+// sound bits on OUT 3 and OUT 5 are taken to be the Invaders ones, only the
+// DIP switches on IN 2 are known to differ. Any extra Part II sound bits are
+// ignored like the unused Invaders bits.
+pub fn invaders_color(cpu: &mut Cpu, ports: &Ports) {
+    invaders(cpu, ports);
+
+    if let Some(ref panel) = cpu.panel {
+        panel.borrow_mut().dips = InvadersDips::with_layout(DipLayout::Color);
+    }
+
+    let color_ram = Rc::new(RefCell::new(ColorRam::new()));
+    cpu.ram.attach(COLOR_RAM_START, COLOR_RAM_END, Box::new(color_ram.clone()));
+    cpu.color_ram = Some(color_ram);
+}
@@ -2,6 +2,15 @@
 
 extern crate minifb;
 extern crate byteorder;
+extern crate clap;
+extern crate png;
+extern crate hound;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate toml;
+extern crate crc32fast;
+extern crate sha1;
+extern crate zip;
 #[macro_use] extern crate text_io;
 
 mod ram;
@@ -9,70 +18,1021 @@ mod opcode;
 mod cpu;
 mod util;
 mod instructions;
+mod trace;
+mod savestate;
+mod profiler;
+mod callgraph;
+mod coverage;
+mod memview;
+mod memmap;
+mod io;
+mod devices;
+mod machine;
+mod audio;
+mod samples;
+mod synth;
+mod config;
+mod bindings;
+mod manifest;
+mod verify;
+mod romsource;
+mod hexfile;
+mod patch;
+mod cpm;
+mod overlay;
 
-use cpu::Cpu;
-use ram::Sram;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::panic;
+use std::path::Path;
+use std::process;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use minifb::Scale;
+use cpu::{Cpu, StopReason};
+use ram::{Sram, MemoryDump, RAM_SIZE};
+use trace::Trace;
+use profiler::Profiler;
+use callgraph::CallGraph;
+use coverage::Coverage;
+use memview::{Activity, MemoryViewer};
+use memmap::{RegionKind, Violation};
+use devices::dips::InvadersDips;
+use devices::sound::SoundLog;
+use devices::watchdog::WatchdogAction;
+use config::{Config, DipConfig};
+use bindings::Bindings;
+use manifest::{Manifest, Orientation, Ports, RomChunk};
+use verify::{Checksums, Match};
+use romsource::RomSource;
+use hexfile::Format;
+use audio::{AudioOutput, AudioSink, PipeSink, SoundSource, WavSink, DEFAULT_SAMPLE_RATE};
+use samples::SamplePlayer;
+use synth::Synthesizer;
+
+const DEFAULT_GAME: &str = "invaders";
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_ERROR: i32 = 3;
 
 fn main() {
-	space_invaders();
-	//test_rom();
-    //baloon_bomber();
-    //lunar_rescue();
+    let matches = app().get_matches_safe().unwrap_or_else(|e| {
+        if e.use_stderr() {
+            eprintln!("{}", e.message);
+            process::exit(EXIT_USAGE);
+        }
+
+        println!("{}", e.message);
+        process::exit(EXIT_OK);
+    });
+
+    // Errors are reported by panicking. Only the message is printed, the
+    // source location and backtrace mean nothing to the user.
+    panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>().cloned()
+            .or_else(|| payload.downcast_ref::<String>().map(|message| message.as_str()))
+            .unwrap_or("unknown error");
+
+        eprintln!("error: {}", message);
+    }));
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| command(&matches)));
+    process::exit(result.unwrap_or(EXIT_ERROR));
 }
 
+fn command(matches: &ArgMatches) -> i32 {
+    match matches.subcommand() {
+        ("run", Some(matches)) => {
+            let manifest = select_game(matches, matches.value_of("rom-dir"));
+            return exit_code(&run_game(&manifest, matches));
+        },
+        ("load", Some(matches)) => return exit_code(&run_game(&load_manifest(matches), matches)),
+        ("com", Some(matches)) => return exit_code(&run_com(matches)),
+        ("verify", Some(matches)) => {
+            return if verify(matches, matches.value_of("path").unwrap()) { EXIT_OK } else { EXIT_FAILURE };
+        },
+        ("dump", Some(matches)) => {
+            let manifest = select_game(matches, matches.value_of("rom-dir"));
+            let (ram, _) = load_game(&manifest, matches);
+            let path = matches.value_of("file").unwrap();
+            memory_dump(path, matches.value_of("range").unwrap(), matches.value_of("format")).write(&ram)
+                .unwrap_or_else(|e| panic!("Unable to write {}: {}", path, e));
+        },
+        ("make-ips", Some(matches)) => {
+            make_ips(matches.value_of("original").unwrap(), matches.value_of("modified").unwrap(), matches.value_of("output").unwrap());
+        },
+        ("games", _) => {
+            for manifest in Manifest::builtin() {
+                println!("{:<16} {:<24} overlays: {}", manifest.name, manifest.description, manifest.overlay_names().join(", "));
+            }
+        },
+        ("bindings", Some(matches)) => {
+            let manifest = select_game(matches, None);
+            print!("{}", bindings(&manifest.name, &load_config(matches)).list());
+        },
+        ("render-sounds", Some(matches)) => {
+            let hash = synth::render_demo(matches.value_of("file").unwrap(), sample_rate(matches)).expect("Unable to render sounds");
+            println!("{:016x}", hash);
+        },
+        _ => unreachable!(),
+    }
 
-fn test_rom() {
-    let rom_path = String::from("C:/TEST.COM");
-    let mut ram: Sram = Sram::new();
-    ram.load_offset(&rom_path, 0x100);
+    EXIT_OK
+}
 
-    let mut cpu: Cpu = Cpu::new(ram);
-    cpu.move_pc(0x100);
-    cpu.ram.write_byte(0x0005, 0xC9);
-    
-    cpu.run();
+fn app() -> App<'static, 'static> {
+    App::new("r8080")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Intel 8080 emulator for Midway 8080 arcade boards")
+        .after_help("Exit codes: 0 on success, 1 when verification fails, 2 for invalid arguments, 3 for errors while loading or running.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(SubCommand::with_name("run")
+            .about("Run a built in game, or the one described by a manifest")
+            .args(&game_args())
+            .args(&rom_args())
+            .args(&machine_args()))
+        .subcommand(SubCommand::with_name("load")
+            .about("Run ROM files loaded at the given addresses on a Midway board")
+            .arg(Arg::with_name("files")
+                .value_name("FILE@OFFSET")
+                .required(true)
+                .multiple(true)
+                .validator(|spec| parse_chunk(&spec).map(|_| ()).ok_or_else(|| format!("{:?} is not FILE@OFFSET with a hex offset", spec)))
+                .help("ROM file and the hex address to load it at, e.g. invaders.h@0"))
+            .arg(Arg::with_name("machine")
+                .long("machine")
+                .takes_value(true)
+                .possible_values(&manifest::MACHINES)
+                .default_value("invaders")
+                .help("Board to run the ROMs on"))
+            .arg(Arg::with_name("memory")
+                .long("memory")
+                .takes_value(true)
+                .possible_values(&manifest::MEMORY_MAPS)
+                .help("Memory map, the board's own by default"))
+            .arg(Arg::with_name("orientation")
+                .long("orientation")
+                .takes_value(true)
+                .possible_values(&["rot0", "rot270"])
+                .default_value("rot270")
+                .help("How the monitor is mounted"))
+            .args(&rom_args())
+            .args(&machine_args()))
+        .subcommand(SubCommand::with_name("com")
+            .about("Run a CP/M .COM program, e.g. a CPU test, with console output")
+            .arg(Arg::with_name("file")
+                .required(true)
+                .help("Program loaded at 0x0100"))
+            .args(&machine_args()))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Check a ROM directory or image against the known sets")
+            .arg(Arg::with_name("path")
+                .required(true)
+                .help("Directory of ROM files, a zip archive or a single concatenated image"))
+            .args(&game_args()))
+        .subcommand(SubCommand::with_name("dump")
+            .about("Write the loaded game's memory to an Intel HEX or S-record file without running it")
+            .arg(Arg::with_name("file")
+                .required(true)
+                .help("Output file"))
+            .args(&game_args())
+            .arg(Arg::with_name("range")
+                .long("range")
+                .value_name("START-END")
+                .default_value("0000-ffff")
+                .validator(range_validator)
+                .help("Inclusive hex address range"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ihex", "srec"])
+                .help("Output format, by default from the file extension"))
+            .args(&rom_args()))
+        .subcommand(SubCommand::with_name("make-ips")
+            .about("Create an IPS patch that turns one ROM image into another")
+            .arg(Arg::with_name("original")
+                .required(true)
+                .help("Unmodified ROM image"))
+            .arg(Arg::with_name("modified")
+                .required(true)
+                .help("Modified ROM image"))
+            .arg(Arg::with_name("output")
+                .required(true)
+                .help("IPS file to write")))
+        .subcommand(SubCommand::with_name("games")
+            .about("List the built in games"))
+        .subcommand(SubCommand::with_name("bindings")
+            .about("List the key bindings for a game")
+            .args(&game_args())
+            .arg(config_arg()))
+        .subcommand(SubCommand::with_name("render-sounds")
+            .about("Render every synthesized sound to a WAV file and print its hash")
+            .arg(Arg::with_name("file")
+                .required(true)
+                .help("WAV file to write"))
+            .arg(sample_rate_arg()))
 }
 
-fn space_invaders() {
-    let rom_path = String::from("invaders.rom");
-    let mut ram: Sram = Sram::new();
-    ram.load(&rom_path);
+fn game_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("game")
+            .help("Built in game, see the games command. Identified from the ROM directory if left out"),
+        Arg::with_name("manifest")
+            .long("manifest")
+            .value_name("FILE")
+            .conflicts_with("game")
+            .help("Use the game described by a manifest file instead of a built in one"),
+    ]
+}
+
+fn rom_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("rom-dir")
+            .long("rom-dir")
+            .value_name("DIR")
+            .default_value(".")
+            .help("Directory holding the ROM files or <game>.zip, or a zip archive"),
+        Arg::with_name("load-hex")
+            .long("load-hex")
+            .value_name("FILE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Load an Intel HEX or S-record file on top of the ROM set, its start address is the default --pc"),
+        Arg::with_name("patch")
+            .long("patch")
+            .value_name("FILE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Apply an IPS or BPS patch to the game's ROM files concatenated in load order, may be given several times"),
+    ]
+}
+
+fn config_arg() -> Arg<'static, 'static> {
+    Arg::with_name("config")
+        .long("config")
+        .value_name("FILE")
+        .help("TOML file with DIP switch and key binding settings, ~/.config/r8080/config.toml by default")
+}
+
+fn sample_rate_arg() -> Arg<'static, 'static> {
+    Arg::with_name("sample-rate")
+        .long("sample-rate")
+        .value_name("HZ")
+        .validator(|rate| rate.parse::<u32>().map(|_| ()).map_err(|_| format!("{:?} is not a sample rate", rate)))
+        .help("Audio output sample rate, 44100 by default")
+}
+
+// Options for everything that runs a machine.
+fn machine_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("scale")
+            .long("scale")
+            .takes_value(true)
+            .possible_values(&["1", "2", "4", "8"])
+            .default_value("1")
+            .help("Window scale"),
+        Arg::with_name("overlay")
+            .long("overlay")
+            .value_name("NAME")
+            .help("Color overlay: mono, or one the game defines such as upright or tv, see the games command"),
+        Arg::with_name("pc")
+            .long("pc")
+            .value_name("ADDRESS")
+            .validator(address_validator)
+            .help("Start executing at this hex address"),
+        Arg::with_name("sp")
+            .long("sp")
+            .value_name("ADDRESS")
+            .validator(address_validator)
+            .help("Initial hex stack pointer"),
+        Arg::with_name("max-instructions")
+            .long("max-instructions")
+            .value_name("COUNT")
+            .validator(count_validator)
+            .help("Stop after executing this many instructions"),
+        Arg::with_name("max-frames")
+            .long("max-frames")
+            .value_name("COUNT")
+            .validator(count_validator)
+            .help("Stop after this many frames of emulated time"),
+        Arg::with_name("trace-depth")
+            .long("trace-depth")
+            .takes_value(true)
+            .validator(count_validator)
+            .help("Number of executed instructions kept for crash backtraces"),
+        Arg::with_name("load-state")
+            .long("load-state")
+            .takes_value(true)
+            .help("Resume from a save state, e.g. one written by a crash dump"),
+        Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .help("Write an execution profile to this file, and a folded flamegraph file next to it"),
+        Arg::with_name("callgraph")
+            .long("callgraph")
+            .takes_value(true)
+            .help("Write the subroutine call graph to <file>.dot and <file>.folded"),
+        Arg::with_name("coverage")
+            .long("coverage")
+            .takes_value(true)
+            .help("Accumulate code coverage in this file and write <file>.asm and <file>.png next to it"),
+        Arg::with_name("coverage-merge")
+            .long("coverage-merge")
+            .takes_value(true)
+            .multiple(true)
+            .requires("coverage")
+            .help("Merge other coverage files into the one given by --coverage"),
+        Arg::with_name("memory-viewer")
+            .long("memory-viewer")
+            .help("Open a second window showing memory reads, writes and executes"),
+        Arg::with_name("rom-write")
+            .long("rom-write")
+            .takes_value(true)
+            .possible_values(&["ignore", "log", "stop"])
+            .help("What to do when the game writes to ROM"),
+        Arg::with_name("unmapped")
+            .long("unmapped")
+            .takes_value(true)
+            .possible_values(&["ignore", "log", "stop"])
+            .help("What to do when the game accesses unmapped memory"),
+        Arg::with_name("unclaimed-ports")
+            .long("unclaimed-ports")
+            .takes_value(true)
+            .possible_values(&["ignore", "log", "stop"])
+            .help("What to do when the game uses an I/O port no device handles"),
+        Arg::with_name("watchdog")
+            .long("watchdog")
+            .takes_value(true)
+            .possible_values(&["reset", "log", "stop"])
+            .help("What to do when the game stops writing to the watchdog port, reset by default"),
+        Arg::with_name("io-log")
+            .long("io-log")
+            .value_name("FILE")
+            .help("Log every IN and OUT with its cycle to a file"),
+        Arg::with_name("sound-log")
+            .long("sound-log")
+            .value_name("FILE")
+            .help("Log sound start and stop events with their cycle to a file"),
+        Arg::with_name("samples")
+            .long("samples")
+            .value_name("DIR")
+            .default_value("samples")
+            .help("Directory holding the sound samples 0.wav to 9.wav"),
+        Arg::with_name("audio-wav")
+            .long("audio-wav")
+            .value_name("FILE")
+            .help("Write the mixed audio to a WAV file"),
+        Arg::with_name("audio-pipe")
+            .long("audio-pipe")
+            .value_name("COMMAND")
+            .conflicts_with("audio-wav")
+            .help("Pipe raw mono S16_LE audio to a player, e.g. \"aplay -q -f S16_LE -r 44100 -c 1\""),
+        Arg::with_name("synth")
+            .long("synth")
+            .help("Synthesize the sounds instead of playing samples"),
+        sample_rate_arg(),
+        config_arg(),
+        Arg::with_name("ships")
+            .long("ships")
+            .takes_value(true)
+            .possible_values(&["3", "4", "5", "6"])
+            .help("DIP switch: ships per game"),
+        Arg::with_name("extra-ship")
+            .long("extra-ship")
+            .takes_value(true)
+            .possible_values(&["1000", "1500"])
+            .help("DIP switch: score for the extra ship"),
+        Arg::with_name("coin-info")
+            .long("coin-info")
+            .takes_value(true)
+            .possible_values(&["on", "off"])
+            .help("DIP switch: show the coin information in the demo"),
+        Arg::with_name("tilt")
+            .long("tilt")
+            .help("Hold the tilt switch closed"),
+        Arg::with_name("cabinet")
+            .long("cabinet")
+            .takes_value(true)
+            .possible_values(&["upright", "cocktail"])
+            .help("Cabinet type, a cocktail table flips the screen for player 2 and gives them their own controls"),
+        Arg::with_name("dump")
+            .long("dump")
+            .value_name("FILE")
+            .help("Write memory to an Intel HEX or S-record file when the machine stops, or when F12 is pressed"),
+        Arg::with_name("dump-at-frame")
+            .long("dump-at-frame")
+            .value_name("FRAME")
+            .requires("dump")
+            .validator(count_validator)
+            .help("Write the --dump file at this frame instead of when the machine stops"),
+        Arg::with_name("dump-range")
+            .long("dump-range")
+            .value_name("START-END")
+            .default_value("0000-ffff")
+            .validator(range_validator)
+            .help("Inclusive hex address range written by --dump"),
+        Arg::with_name("dump-format")
+            .long("dump-format")
+            .takes_value(true)
+            .possible_values(&["ihex", "srec"])
+            .help("Format written by --dump, by default from the file extension"),
+    ]
+}
+
+fn address_validator(address: String) -> Result<(), String> {
+    parse_address(&address).map(|_| ()).ok_or_else(|| format!("{:?} is not a hex address", address))
+}
+
+fn range_validator(range: String) -> Result<(), String> {
+    parse_range(&range).map(|_| ()).ok_or_else(|| format!("{:?} is not a hex address range like 2000-23ff", range))
+}
+
+fn count_validator(count: String) -> Result<(), String> {
+    count.parse::<u64>().map(|_| ()).map_err(|_| format!("{:?} is not a number", count))
+}
+
+// Without a game the ROM directory is identified, falling back to the default game.
+fn select_game(matches: &ArgMatches, rom_dir: Option<&str>) -> Manifest {
+    if let Some(path) = matches.value_of("manifest") {
+        return Manifest::load(path).unwrap_or_else(|e| panic!("Unable to load manifest {}: {}", path, e));
+    }
+
+    let name = match (matches.value_of("game"), rom_dir) {
+        (Some(name), _) => name,
+        (None, Some(dir)) => {
+            let found = verify::identify_dir(dir);
+
+            if let Some((manifest, statuses)) = found.into_iter().find(|(_, statuses)| Match::of(statuses) != Match::Incomplete) {
+                if Match::of(&statuses) == Match::Unverified {
+                    println!("Guessing {} from file names and sizes, it has no known checksums", manifest.name);
+                }
+
+                return manifest;
+            }
+
+            DEFAULT_GAME
+        },
+        (None, None) => DEFAULT_GAME,
+    };
+
+    Manifest::find(name).unwrap_or_else(|| panic!("Unknown game {:?}, see the games command", name))
+}
+
+// A manifest for the files given to the load command, without checksums. It
+// describes the board and lets the chunks be checked for overlaps, the files
+// themselves are loaded as they are by `load_game`.
+fn load_manifest(matches: &ArgMatches) -> Manifest {
+    let dir = Path::new(matches.value_of("rom-dir").unwrap());
+    let machine = matches.value_of("machine").unwrap();
+
+    let roms = matches.values_of("files").unwrap().map(|spec| {
+        let (file, offset) = parse_chunk(spec).unwrap();
+        let size = fs::metadata(dir.join(file)).unwrap_or_else(|e| panic!("Unable to read {}: {}", file, e)).len() as usize;
+
+        RomChunk { file: file.to_string(), offset, size, crc32: None, sha1: None }
+    }).collect();
+
+    let manifest = Manifest {
+        name: String::from("load"),
+        description: String::from("r8080"),
+        machine: machine.to_string(),
+        memory: matches.value_of("memory").unwrap_or_else(|| manifest::default_memory(machine)).to_string(),
+        orientation: if matches.value_of("orientation") == Some("rot0") { Orientation::Rot0 } else { Orientation::Rot270 },
+        roms,
+        ports: Ports::default(),
+        dip: DipConfig::default(),
+        overlay: None,
+        overlays: BTreeMap::new(),
+        overlays_from: None,
+    };
+
+    manifest.validate().unwrap_or_else(|e| panic!("{}", e));
+    manifest
+}
+
+fn verify(matches: &ArgMatches, path: &str) -> bool {
+    if Path::new(path).is_file() && !is_zip(path) {
+        let bytes = verify::read(Path::new(path)).unwrap_or_else(|e| panic!("Unable to read {}: {}", path, e));
+        let sets = verify::identify_image(&bytes);
+
+        if sets.is_empty() {
+            let checksums = Checksums::new(&bytes);
+            println!("{}: unknown image, {:#x} bytes, crc32 {:08x} sha1 {}", path, bytes.len(), checksums.crc32, checksums.sha1);
+            return false;
+        }
+
+        for manifest in sets {
+            println!("{}: {} ({})", path, manifest.name, manifest.description);
+        }
+
+        return true;
+    }
+
+    if matches.is_present("game") || matches.is_present("manifest") {
+        let manifest = select_game(matches, None);
+        let statuses = verify::verify_dir(&manifest, path);
+        print!("{}", verify::report(&manifest, &statuses));
+
+        return Match::of(&statuses) == Match::Verified;
+    }
+
+    let found = verify::identify_dir(path);
+
+    if found.is_empty() {
+        println!("No known ROM set in {}", path);
+        return false;
+    }
+
+    for (manifest, statuses) in found.iter() {
+        print!("{}", verify::report(manifest, statuses));
+    }
+
+    // Without checksums a set can look complete and still be a bad dump.
+    match found.iter().map(|(manifest, statuses)| (manifest, Match::of(statuses))).find(|&(_, found)| found != Match::Incomplete) {
+        Some((manifest, Match::Verified)) => {
+            println!("Identified as {}", manifest.name);
+            true
+        },
+        Some((manifest, _)) => {
+            println!("File names and sizes match {}, but it has no known checksums to verify the dumps", manifest.name);
+            false
+        },
+        None => false,
+    }
+}
+
+fn is_zip(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+fn load_config(matches: &ArgMatches) -> Config {
+    if let Some(path) = matches.value_of("config") {
+        return Config::load(path).unwrap_or_else(|e| panic!("Unable to load config {}: {}", path, e));
+    }
+
+    match Config::user_path() {
+        Some(ref path) if path.exists() => Config::load(&path.to_string_lossy())
+            .unwrap_or_else(|e| panic!("Unable to load config {}: {}", path.display(), e)),
+        _ => Config::default(),
+    }
+}
+
+// Built in bindings, then the config's defaults, then its bindings for the game.
+fn bindings(game: &str, config: &Config) -> Bindings {
+    Bindings::configured(game, config).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn configure(cpu: &mut Cpu, game: &str, matches: &ArgMatches) {
+    let config = load_config(matches);
+    cpu.bindings = bindings(game, &config);
+
+    if let Some(ref panel) = cpu.panel {
+        configure_dips(&mut panel.borrow_mut().dips, &config, matches).unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    if let Some(depth) = matches.value_of("trace-depth") {
+        let depth = depth.parse().expect("Invalid trace depth");
+        cpu.trace = Trace::new(depth);
+    }
+
+    if let Some(path) = matches.value_of("load-state") {
+        cpu.load_state(path).expect("Unable to load save state");
+    }
+
+    if matches.is_present("profile") {
+        cpu.profiler = Some(Profiler::new());
+    }
+
+    if matches.is_present("callgraph") {
+        cpu.call_graph = Some(CallGraph::new());
+    }
+
+    if let Some(path) = matches.value_of("coverage") {
+        // A missing file starts a new run, anything else would be overwritten.
+        let mut coverage = match Coverage::load(path) {
+            Ok(coverage) => coverage,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Coverage::new(),
+            Err(e) => panic!("Unable to load coverage file {}: {}", path, e),
+        };
+
+        if let Some(paths) = matches.values_of("coverage-merge") {
+            for path in paths {
+                coverage.merge(&Coverage::load(path).expect("Unable to load coverage file"));
+            }
+        }
+
+        cpu.ram.coverage = Some(coverage);
+    }
+
+    if let Some(violation) = matches.value_of("rom-write").and_then(Violation::parse) {
+        cpu.ram.map.set_violation(RegionKind::Rom, violation);
+    }
+
+    if let Some(violation) = matches.value_of("unmapped").and_then(Violation::parse) {
+        cpu.ram.map.set_violation(RegionKind::Unmapped, violation);
+    }
+
+    if let Some(violation) = matches.value_of("unclaimed-ports").and_then(Violation::parse) {
+        cpu.io.unclaimed_policy = violation;
+    }
+
+    if let (Some(watchdog), Some(action)) = (cpu.watchdog.as_ref(), matches.value_of("watchdog").and_then(WatchdogAction::parse)) {
+        watchdog.borrow_mut().action = action;
+    }
+
+    if let Some(path) = matches.value_of("io-log") {
+        cpu.io.open_log(path).expect("Unable to open I/O log");
+    }
+
+    if let Some(path) = matches.value_of("sound-log") {
+        let sound = cpu.sound.clone().expect("This machine has no sound board to log");
+        let sound_log = SoundLog::new(&mut sound.borrow_mut(), path).expect("Unable to open sound log");
+        cpu.sound_log = Some(sound_log);
+    }
+
+    let sample_rate = sample_rate(matches);
+
+    let sink: Option<Box<dyn AudioSink>> = if let Some(path) = matches.value_of("audio-wav") {
+        Some(Box::new(WavSink::new(path, sample_rate).expect("Unable to create WAV file")))
+    } else if let Some(command) = matches.value_of("audio-pipe") {
+        Some(Box::new(PipeSink::new(command).expect("Unable to start audio player")))
+    } else {
+        None
+    };
+
+    if let Some(sink) = sink {
+        let sound = cpu.sound.clone().expect("This machine has no sound board to play");
+        let source: Box<dyn SoundSource> = if matches.is_present("synth") {
+            Box::new(Synthesizer::new(sample_rate))
+        } else {
+            Box::new(SamplePlayer::load(matches.value_of("samples").unwrap(), sample_rate))
+        };
+
+        cpu.audio = Some(AudioOutput::new(&mut sound.borrow_mut(), source, sink, sample_rate));
+    }
+
+    if matches.is_present("memory-viewer") {
+        cpu.ram.activity = Some(Activity::new());
+        cpu.memory_viewer = Some(MemoryViewer::new());
+    }
+
+    if let Some(pc) = matches.value_of("pc").and_then(parse_address) {
+        cpu.move_pc(pc);
+    }
+
+    if let Some(sp) = matches.value_of("sp").and_then(parse_address) {
+        cpu.sp = sp;
+    }
+
+    if let Some(path) = matches.value_of("dump") {
+        cpu.memory_dump = Some(memory_dump(path, matches.value_of("dump-range").unwrap(), matches.value_of("dump-format")));
+        cpu.dump_at_frame = matches.value_of("dump-at-frame").map(|frame| frame.parse().unwrap());
+    }
 
-    let mut cpu: Cpu = Cpu::new(ram);
-   
-    cpu.run();
+    cpu.max_instructions = matches.value_of("max-instructions").map(|count| count.parse().unwrap());
+    cpu.max_frames = matches.value_of("max-frames").map(|count| count.parse().unwrap());
 }
 
-fn baloon_bomber() {
-    //let rom_path = String::from("bal.rom");
+fn scale(matches: &ArgMatches) -> Scale {
+    match matches.value_of("scale") {
+        Some("2") => Scale::X2,
+        Some("4") => Scale::X4,
+        Some("8") => Scale::X8,
+        _ => Scale::X1,
+    }
+}
+
+fn configure_dips(dips: &mut InvadersDips, config: &Config, matches: &ArgMatches) -> Result<(), String> {
+    apply_dips(dips, &config.dip)?;
+
+    apply_dips(dips, &DipConfig {
+        ships: matches.value_of("ships").map(|ships| ships.parse().unwrap()),
+        extra_ship_at: matches.value_of("extra-ship").map(|score| score.parse().unwrap()),
+        coin_info: matches.value_of("coin-info").map(|value| value == "on"),
+        tilt: if matches.is_present("tilt") { Some(true) } else { None },
+        cocktail: matches.value_of("cabinet").map(|cabinet| cabinet == "cocktail"),
+    })
+}
+
+// Changes only the settings that are given.
+fn apply_dips(dips: &mut InvadersDips, settings: &DipConfig) -> Result<(), String> {
+    if let Some(ships) = settings.ships {
+        dips.set_ships(ships)?;
+    }
+
+    if let Some(score) = settings.extra_ship_at {
+        dips.set_extra_ship_at(score)?;
+    }
+
+    if let Some(coin_info) = settings.coin_info {
+        dips.coin_info = coin_info;
+    }
+
+    if let Some(tilt) = settings.tilt {
+        dips.tilt = tilt;
+    }
+
+    if let Some(cocktail) = settings.cocktail {
+        dips.cocktail = cocktail;
+    }
+
+    Ok(())
+}
+
+fn sample_rate(matches: &ArgMatches) -> u32 {
+    matches.value_of("sample-rate").map_or(DEFAULT_SAMPLE_RATE, |rate| rate.parse().expect("Invalid sample rate"))
+}
+
+fn finish(cpu: &mut Cpu, matches: &ArgMatches) {
+    if let Some(ref mut sound_log) = cpu.sound_log {
+        sound_log.drain().expect("Unable to write sound log");
+    }
+
+    if let Some(ref mut audio) = cpu.audio {
+        audio.finish(cpu.total_cycles).expect("Unable to write audio");
+    }
+
+    if let (Some(profiler), Some(path)) = (cpu.profiler.as_ref(), matches.value_of("profile")) {
+        profiler.write(&cpu.ram, path).expect("Unable to write profile");
+    }
+
+    if let (Some(call_graph), Some(path)) = (cpu.call_graph.as_ref(), matches.value_of("callgraph")) {
+        call_graph.write(path).expect("Unable to write call graph");
+    }
+
+    if let (Some(coverage), Some(path)) = (cpu.ram.coverage.as_ref(), matches.value_of("coverage")) {
+        coverage.save(path).expect("Unable to write coverage");
+        File::create(format!("{}.asm", path))
+            .and_then(|mut f| f.write_all(coverage.annotated_disassembly(&cpu.ram).as_bytes()))
+            .expect("Unable to write annotated disassembly");
+        coverage.write_heatmap(&cpu.ram, &format!("{}.png", path)).expect("Unable to write coverage heatmap");
+    }
+
+    if cpu.dump_at_frame.is_none() {
+        cpu.write_memory_dump();
+    }
+
+    if cpu.io.unclaimed_policy != Violation::Ignore {
+        print!("{}", cpu.io.report());
+    }
+}
+
+fn memory_dump(path: &str, range: &str, format: Option<&str>) -> MemoryDump {
+    let (start, end) = parse_range(range).unwrap_or_else(|| panic!("Invalid address range {:?}, expected e.g. 2000-23ff", range));
+    let format = format.and_then(Format::parse)
+        .or_else(|| Format::from_path(path))
+        .unwrap_or(Format::IntelHex);
+
+    MemoryDump {
+        path: String::from(path),
+        start,
+        end,
+        format,
+    }
+}
+
+// Patches see the manifest's ROM chunks concatenated in order, like the ROM
+// files joined with cat, so RAM between the chunks is not part of the image.
+// The result is spread back over the chunks, anything past the last one
+// follows it in memory.
+fn apply_patches(ram: &mut Sram, roms: &[RomChunk], paths: Vec<&str>) {
+    let mut image: Vec<u8> = roms.iter()
+        .flat_map(|rom| ram.bytes[rom.offset as usize..rom.offset as usize + rom.size].to_vec())
+        .collect();
+
+    for path in paths {
+        let patch = verify::read(Path::new(path)).unwrap_or_else(|e| panic!("Unable to read patch {}: {}", path, e));
+        image = patch::apply(&image, &patch).unwrap_or_else(|e| panic!("Unable to apply patch {}: {}", path, e));
+        println!("Applied patch {}", path);
+    }
+
+    let mut rest = &image[..];
+
+    for (index, rom) in roms.iter().enumerate() {
+        let size = if index + 1 == roms.len() { rest.len() } else { rom.size.min(rest.len()) };
+        let start = rom.offset as usize;
+
+        if start + size > RAM_SIZE {
+            panic!("Patched ROM image is {:#x} bytes, larger than the address space", image.len());
+        }
+
+        for byte in ram.bytes[start..start + rom.size].iter_mut() {
+            *byte = 0;
+        }
+
+        ram.load_bytes(&rest[..size], rom.offset);
+        rest = &rest[size..];
+    }
+}
+
+fn make_ips(original: &str, modified: &str, output: &str) {
+    let read = |path: &str| verify::read(Path::new(path)).unwrap_or_else(|e| panic!("Unable to read {}: {}", path, e));
+    let patch = patch::create_ips(&read(original), &read(modified));
+
+    File::create(output)
+        .and_then(|mut f| f.write_all(&patch))
+        .unwrap_or_else(|e| panic!("Unable to write {}: {}", output, e));
+}
+
+fn parse_address(address: &str) -> Option<u16> {
+    u16::from_str_radix(address.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    let mut parts = range.splitn(2, '-').map(parse_address);
+
+    match (parts.next(), parts.next()) {
+        (Some(Some(start)), Some(Some(end))) if start <= end => Some((start, end)),
+        _ => None,
+    }
+}
+
+// "file@offset", split at the last @ so file names may contain one.
+fn parse_chunk(spec: &str) -> Option<(&str, u16)> {
+    let at = spec.rfind('@')?;
+    let offset = parse_address(&spec[at + 1..])?;
+
+    if at == 0 {
+        return None;
+    }
+
+    Some((&spec[..at], offset))
+}
+
+// Stops asked for by the options, e.g. --unmapped stop, count as errors.
+fn exit_code(reason: &StopReason) -> i32 {
+    if reason.is_error() { EXIT_ERROR } else { EXIT_OK }
+}
+
+fn run_com(matches: &ArgMatches) -> StopReason {
+    let path = matches.value_of("file").unwrap();
+    let program = verify::read(Path::new(path)).unwrap_or_else(|e| panic!("Unable to read {}: {}", path, e));
+
+    if program.len() > RAM_SIZE - cpm::TPA as usize {
+        panic!("{} is too large for CP/M", path);
+    }
+
     let mut ram: Sram = Sram::new();
-    
-    ram.load_offset("C:\\ballbomb\\tn01", 0x0000);
-    ram.load_offset("C:\\ballbomb\\tn02", 0x0800);
-    ram.load_offset("C:\\ballbomb\\tn03", 0x1000);
-    ram.load_offset("C:\\ballbomb\\tn04", 0x1800);
-    ram.load_offset("C:\\ballbomb\\tn05-1", 0x4000);
-    //ram.load(&rom_path);
+    ram.load_bytes(&program, cpm::TPA);
+
+    // A console program, so there is no window to open.
+    let mut cpu: Cpu = Cpu::headless(ram);
+    cpu.boot_cpm();
+    configure(&mut cpu, "com", matches);
 
-    let mut cpu: Cpu = Cpu::new(ram);
-   
-    cpu.run();
+    let reason = cpu.run();
+    finish(&mut cpu, matches);
+    reason
 }
 
-fn lunar_rescue() {
-    //let rom_path = String::from("bal.rom");
+// The RAM and the start address of the last HEX file that gives one.
+fn load_game(manifest: &Manifest, matches: &ArgMatches) -> (Sram, Option<u16>) {
     let mut ram: Sram = Sram::new();
-    
-    ram.load_offset("C:\\lrescue\\lrescue.1", 0x0000);
-    ram.load_offset("C:\\lrescue\\lrescue.2", 0x0800);
-    ram.load_offset("C:\\lrescue\\lrescue.3", 0x1000);
-    ram.load_offset("C:\\lrescue\\lrescue.4", 0x1800);
-    ram.load_offset("C:\\lrescue\\lrescue.5", 0x4000);
-    ram.load_offset("C:\\lrescue\\lrescue.6", 0x4800);
-
-    //ram.load(&rom_path);
-
-    let mut cpu: Cpu = Cpu::new(ram);
-   
-    cpu.run();
+    let dir = matches.value_of("rom-dir").unwrap();
+
+    if let Some(specs) = matches.values_of("files") {
+        for spec in specs {
+            let (file, offset) = parse_chunk(spec).unwrap();
+            ram.load_offset(&Path::new(dir).join(file).to_string_lossy(), offset);
+        }
+    } else {
+        let mut source = RomSource::open(dir, manifest)
+            .unwrap_or_else(|e| panic!("Unable to open ROMs for {}: {}", manifest.name, e));
+        manifest.load_roms(&mut ram, &mut source)
+            .unwrap_or_else(|e| panic!("Unable to load {}: {}", manifest.name, e));
+    }
+
+    let mut start = None;
+
+    if let Some(paths) = matches.values_of("load-hex") {
+        for path in paths {
+            start = ram.load_hex(path).unwrap_or_else(|e| panic!("Unable to load {}: {}", path, e)).or(start);
+        }
+    }
+
+    if let Some(paths) = matches.values_of("patch") {
+        apply_patches(&mut ram, &manifest.roms, paths.collect());
+    }
+
+    ram.map = manifest.memory_map().unwrap();
+    (ram, start)
+}
+
+fn run_game(manifest: &Manifest, matches: &ArgMatches) -> StopReason {
+    let (ram, start) = load_game(manifest, matches);
+
+    let mut cpu: Cpu = Cpu::new(ram, &manifest.description, manifest.orientation, scale(matches));
+    machine::build(&mut cpu, manifest);
+
+    // --pc still wins over the start address.
+    if let Some(start) = start {
+        cpu.move_pc(start);
+    }
+
+    let overlay = matches.value_of("overlay").or(manifest.overlay.as_deref());
+    if let Some(name) = overlay {
+        cpu.set_overlay(manifest.find_overlay(name).unwrap_or_else(|e| panic!("{}", e)));
+    }
+
+    if let Some(ref panel) = cpu.panel {
+        apply_dips(&mut panel.borrow_mut().dips, &manifest.dip).unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    configure(&mut cpu, &manifest.name, matches);
+
+    let reason = cpu.run();
+    finish(&mut cpu, matches);
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_hex_with_optional_prefix() {
+        assert_eq!(parse_address("1f00"), Some(0x1f00));
+        assert_eq!(parse_address("0x1F00"), Some(0x1f00));
+        assert_eq!(parse_address("ffff"), Some(0xffff));
+        assert_eq!(parse_address("0x"), None);
+        assert_eq!(parse_address(""), None);
+        assert_eq!(parse_address("10000"), None);
+        assert_eq!(parse_address("12g4"), None);
+    }
+
+    #[test]
+    fn ranges_are_inclusive_and_ordered() {
+        assert_eq!(parse_range("2000-23ff"), Some((0x2000, 0x23ff)));
+        assert_eq!(parse_range("0x2000-0x2000"), Some((0x2000, 0x2000)));
+        assert_eq!(parse_range("2000-1fff"), None);
+        assert_eq!(parse_range("2000"), None);
+        assert_eq!(parse_range("2000-"), None);
+        assert_eq!(parse_range("-23ff"), None);
+    }
+
+    #[test]
+    fn chunks_split_at_the_last_at_sign() {
+        assert_eq!(parse_chunk("invaders.h@0"), Some(("invaders.h", 0x0000)));
+        assert_eq!(parse_chunk("roms/a@b@0x4000"), Some(("roms/a@b", 0x4000)));
+        assert_eq!(parse_chunk("@100"), None);
+        assert_eq!(parse_chunk("invaders.h@"), None);
+        assert_eq!(parse_chunk("invaders.h"), None);
+        assert_eq!(parse_chunk("invaders.h@10000"), None);
+    }
+
+    #[test]
+    fn reloading_a_dump_keeps_the_pc() {
+        let dir = std::env::temp_dir().join(format!("r8080-{}-dump-reload", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("rom"), [0x00; 16]).unwrap();
+
+        let mut ram = Sram::new();
+        ram.load_bytes(&[0x12, 0x34, 0x56], 0x2000);
+
+        for name in ["ram.hex", "ram.s19"].iter() {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            memory_dump(&path, "2000-23ff", None).write(&ram).unwrap();
+
+            let args = vec!["r8080", "load", "rom@0", "--rom-dir", dir.to_str().unwrap(), "--load-hex", &path];
+            let matches = app().get_matches_from(args);
+            let matches = matches.subcommand_matches("load").unwrap();
+            let (loaded, start) = load_game(&load_manifest(matches), matches);
+
+            assert_eq!(loaded.dump(0x2000, 0x23ff), ram.dump(0x2000, 0x23ff), "{}", name);
+            assert_eq!(start.unwrap_or(0x0000), 0x0000, "{}", name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn patches_skip_the_ram_between_midway_chunks() {
+        let manifest = Manifest::find("lrescue").unwrap();
+        let mut ram = Sram::new();
+
+        for (index, rom) in manifest.roms.iter().enumerate() {
+            ram.load_bytes(&vec![index as u8 + 1; rom.size], rom.offset);
+        }
+        ram.bytes[0x2000] = 0xee;
+
+        // Change the first byte of lrescue.5, 0x2000 into the concatenated files.
+        let original: Vec<u8> = manifest.roms.iter().flat_map(|rom| ram.dump(rom.offset, rom.offset + rom.size as u16 - 1)).collect();
+        let mut modified = original.clone();
+        modified[0x2000] = 0x99;
+
+        let path = std::env::temp_dir().join(format!("r8080-{}-lrescue.ips", process::id()));
+        fs::write(&path, patch::create_ips(&original, &modified)).unwrap();
+        apply_patches(&mut ram, &manifest.roms, vec![path.to_str().unwrap()]);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(ram.bytes[0x4000], 0x99);
+        assert_eq!(ram.bytes[0x4001], 0x05);
+        assert_eq!(ram.bytes[0x1fff], 0x04);
+        assert_eq!(ram.bytes[0x2000], 0xee);
+    }
 }
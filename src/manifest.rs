@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+
+use toml;
+
+use config::DipConfig;
+use memmap::MemoryMap;
+use overlay::{self, Overlay};
+use ram::{Sram, RAM_SIZE};
+use romsource::RomSource;
+use verify::{self, Status};
+
+// Games that ship with the emulator, see games/.
+const BUILTIN: [&str; 5] = [
+    include_str!("../games/invaders.toml"),
+    include_str!("../games/invaders-merged.toml"),
+    include_str!("../games/invadpt2.toml"),
+    include_str!("../games/lrescue.toml"),
+    include_str!("../games/ballbomb.toml"),
+];
+
+pub const MACHINES: [&str; 3] = ["midway", "invaders", "invaders-color"];
+pub const MEMORY_MAPS: [&str; 3] = ["invaders", "midway", "flat"];
+
+// The memory map a board has unless told otherwise.
+pub fn default_memory(machine: &str) -> &str {
+    match machine {
+        "invaders" => "invaders",
+        _ => "midway",
+    }
+}
+
+// How the monitor is mounted. Midway cabinets have it turned 90 degrees.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Rot0,
+    #[default]
+    Rot270,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RomChunk {
+    pub file: String,
+    pub offset: u16,
+    pub size: usize,
+    pub crc32: Option<String>,
+    pub sha1: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ports {
+    pub panel: [u8; 2],
+    pub shift_offset: u8,
+    pub shift_data: u8,
+    pub shift_result: u8,
+    pub watchdog: u8,
+}
+
+impl Default for Ports {
+    fn default() -> Ports {
+        Ports {
+            panel: [0x01, 0x02],
+            shift_offset: 0x02,
+            shift_data: 0x04,
+            shift_result: 0x03,
+            watchdog: 0x06,
+        }
+    }
+}
+
+// Everything needed to run one ROM set.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    pub description: String,
+    pub machine: String,
+    pub memory: String,
+    #[serde(default)]
+    pub orientation: Orientation,
+    #[serde(rename = "rom")]
+    pub roms: Vec<RomChunk>,
+    #[serde(default)]
+    pub ports: Ports,
+    #[serde(default)]
+    pub dip: DipConfig,
+    // Overlay used unless another is picked, monochrome if not given.
+    pub overlay: Option<String>,
+    #[serde(default)]
+    pub overlays: BTreeMap<String, Overlay>,
+    // Built in game whose overlays this one shares, e.g. a different dump
+    // of the same game. Its own overlays win on a name clash.
+    pub overlays_from: Option<String>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let mut manifest: Manifest = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        if let Some(ref name) = manifest.overlays_from {
+            // Not followed any further, so games can't include each other in a loop.
+            let shared: Manifest = BUILTIN.iter()
+                .filter_map(|text| toml::from_str::<Manifest>(text).ok())
+                .find(|other| other.name == *name)
+                .ok_or_else(|| format!("overlays_from names unknown game {:?}", name))?;
+
+            for (name, overlay) in shared.overlays {
+                manifest.overlays.entry(name).or_insert(overlay);
+            }
+        }
+
+        manifest.validate()?;
+
+        Ok(manifest)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !MACHINES.contains(&self.machine.as_str()) {
+            return Err(format!("Unknown machine {:?}", self.machine));
+        }
+
+        self.memory_map()?;
+
+        for rom in self.roms.iter() {
+            if rom.offset as usize + rom.size > RAM_SIZE {
+                return Err(format!("{} does not fit at {:#06x}", rom.file, rom.offset));
+            }
+        }
+
+        for (name, overlay) in self.overlays.iter() {
+            overlay.validate().map_err(|e| format!("Overlay {}: {}", name, e))?;
+        }
+
+        if let Some(ref name) = self.overlay {
+            self.find_overlay(name)?;
+        }
+
+        Ok(())
+    }
+
+    // None is plain monochrome.
+    pub fn find_overlay(&self, name: &str) -> Result<Option<&Overlay>, String> {
+        if name == overlay::MONOCHROME {
+            return Ok(None);
+        }
+
+        self.overlays.get(name).map(Some).ok_or_else(|| {
+            format!("{} has no overlay {:?}, choose from {}", self.name, name, self.overlay_names().join(", "))
+        })
+    }
+
+    pub fn overlay_names(&self) -> Vec<&str> {
+        let mut names = vec![overlay::MONOCHROME];
+        names.extend(self.overlays.keys().map(|name| name.as_str()));
+        names
+    }
+
+    pub fn load(file_name: &str) -> io::Result<Manifest> {
+        let mut text = String::new();
+        File::open(file_name)?.read_to_string(&mut text)?;
+
+        Manifest::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn builtin() -> Vec<Manifest> {
+        BUILTIN.iter()
+            .map(|text| Manifest::parse(text).expect("Invalid built in manifest"))
+            .collect()
+    }
+
+    pub fn find(name: &str) -> Option<Manifest> {
+        Manifest::builtin().into_iter().find(|manifest| manifest.name == name)
+    }
+
+    pub fn memory_map(&self) -> Result<MemoryMap, String> {
+        match self.memory.as_str() {
+            "invaders" => Ok(MemoryMap::invaders()),
+            "midway" => Ok(MemoryMap::midway()),
+            "flat" => Ok(MemoryMap::flat()),
+            other => Err(format!("Unknown memory map {:?}", other)),
+        }
+    }
+
+    pub fn load_roms(&self, ram: &mut Sram, source: &mut RomSource) -> io::Result<()> {
+        for rom in self.roms.iter() {
+            let name = source.name(&rom.file);
+            let bytes = source.read(&rom.file)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?;
+
+            // A bad dump is still loaded, it may be a deliberate hack.
+            match verify::check(rom, &bytes) {
+                Status::WrongSize(_) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("{} is {:#x} bytes, expected {:#x}", name, bytes.len(), rom.size))),
+                status @ Status::BadDump(_) => println!("Warning: {} {}", name, status.describe(rom)),
+                _ => (),
+            }
+
+            ram.load_bytes(&bytes, rom.offset);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_invaders_shares_the_overlays() {
+        let invaders = Manifest::find("invaders").unwrap();
+        let merged = Manifest::find("invaders-merged").unwrap();
+
+        assert_eq!(merged.overlay_names(), invaders.overlay_names());
+        assert_eq!(merged.overlays["upright"].rects.len(), invaders.overlays["upright"].rects.len());
+    }
+
+    #[test]
+    fn every_machine_has_a_memory_map() {
+        for machine in MACHINES.iter() {
+            assert!(MEMORY_MAPS.contains(&default_memory(machine)));
+        }
+    }
+
+    #[test]
+    fn overlays_from_must_name_a_builtin_game() {
+        let text = "name = \"test\"\ndescription = \"Test\"\nmachine = \"midway\"\nmemory = \"midway\"\nrom = []\noverlays_from = \"nothing\"\n";
+
+        assert!(Manifest::parse(text).unwrap_err().contains("unknown game \"nothing\""));
+    }
+
+    fn is_hex(text: &str, digits: usize) -> bool {
+        text.len() == digits && text.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    #[test]
+    fn checksums_come_in_well_formed_pairs() {
+        for manifest in Manifest::builtin() {
+            for rom in manifest.roms.iter() {
+                match (rom.crc32.as_ref(), rom.sha1.as_ref()) {
+                    (Some(crc32), Some(sha1)) => {
+                        assert!(is_hex(crc32, 8), "{} {} crc32 {:?}", manifest.name, rom.file, crc32);
+                        assert!(is_hex(sha1, 40), "{} {} sha1 {:?}", manifest.name, rom.file, sha1);
+                    },
+                    (None, None) => (),
+                    _ => panic!("{} {} has only one of crc32 and sha1", manifest.name, rom.file),
+                }
+            }
+        }
+    }
+
+    // Run with --ignored once the reference checksums have been added.
+    #[test]
+    #[ignore = "no reference checksums for ballbomb, invadpt2 and lrescue yet"]
+    fn every_builtin_chunk_has_checksums() {
+        for manifest in Manifest::builtin() {
+            for rom in manifest.roms.iter() {
+                assert!(rom.crc32.is_some() && rom.sha1.is_some(), "{} {} has no checksums", manifest.name, rom.file);
+            }
+        }
+    }
+}
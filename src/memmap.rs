@@ -0,0 +1,314 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use ram::RAM_SIZE;
+
+// What happens on a write to ROM or any access to unmapped space.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Violation {
+    Ignore,
+    Log,
+    Stop,
+}
+
+impl Violation {
+    pub fn parse(name: &str) -> Option<Violation> {
+        match name {
+            "ignore" => Some(Violation::Ignore),
+            "log" => Some(Violation::Log),
+            "stop" => Some(Violation::Stop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RegionKind {
+    Rom,
+    Ram,
+    // Same size window onto the region starting at the given address.
+    Mirror(u16),
+    // Index into the devices attached to `Sram`.
+    Device(usize),
+    Unmapped,
+}
+
+// Where an access ends up after mirrors have been resolved.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    Memory(usize),
+    // Device index and offset from the start of its region.
+    Device(usize, u16),
+}
+
+// Memory mapped hardware. Reads may have side effects, `peek` must not.
+pub trait MemoryDevice {
+    fn read(&mut self, offset: u16, cycle: u64) -> u8;
+    fn write(&mut self, offset: u16, value: u8, cycle: u64);
+    fn peek(&self, offset: u16) -> u8;
+    // Back to the power-on state when the machine is reset.
+    fn reset(&mut self, _cycle: u64) {}
+    // Device contents for save states.
+    fn save(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+    fn load(&mut self, _input: &mut dyn Read, _cycle: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Shared devices, e.g. color RAM that the renderer reads as well.
+impl<T: MemoryDevice> MemoryDevice for Rc<RefCell<T>> {
+    fn read(&mut self, offset: u16, cycle: u64) -> u8 {
+        self.borrow_mut().read(offset, cycle)
+    }
+
+    fn write(&mut self, offset: u16, value: u8, cycle: u64) {
+        self.borrow_mut().write(offset, value, cycle)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.borrow().peek(offset)
+    }
+
+    fn reset(&mut self, cycle: u64) {
+        self.borrow_mut().reset(cycle)
+    }
+
+    fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.borrow().save(out)
+    }
+
+    fn load(&mut self, input: &mut dyn Read, cycle: u64) -> io::Result<()> {
+        self.borrow_mut().load(input, cycle)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub start: u16,
+    pub kind: RegionKind,
+    pub violation: Violation,
+}
+
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+    lookup: Vec<u8>,
+}
+
+impl MemoryMap {
+    pub fn new() -> MemoryMap {
+        let mut map = MemoryMap {
+            regions: Vec::new(),
+            lookup: vec![0; RAM_SIZE],
+        };
+
+        map.add(0x0000, 0xffff, RegionKind::Unmapped);
+        map
+    }
+
+    pub fn flat() -> MemoryMap {
+        let mut map = MemoryMap::new();
+        map.add(0x0000, 0xffff, RegionKind::Ram);
+        map
+    }
+
+    pub fn invaders() -> MemoryMap {
+        let mut map = MemoryMap::new();
+        map.add(0x0000, 0x1fff, RegionKind::Rom);
+        map.add(0x2000, 0x3fff, RegionKind::Ram);
+        map.add(0x4000, 0x5fff, RegionKind::Mirror(0x2000));
+        map
+    }
+
+    // Later Midway boards with a second bank of ROM at 0x4000.
+    pub fn midway() -> MemoryMap {
+        let mut map = MemoryMap::new();
+        map.add(0x0000, 0x1fff, RegionKind::Rom);
+        map.add(0x2000, 0x3fff, RegionKind::Ram);
+        map.add(0x4000, 0x5fff, RegionKind::Rom);
+        map
+    }
+
+    // Later regions take precedence over earlier ones where they overlap.
+    pub fn add(&mut self, start: u16, end: u16, kind: RegionKind) {
+        let index = self.regions.len();
+
+        if index > u8::MAX as usize {
+            panic!("Too many memory regions");
+        }
+
+        self.regions.push(Region {
+            start,
+            kind,
+            violation: Violation::Ignore,
+        });
+
+        for address in start as usize..=end as usize {
+            self.lookup[address] = index as u8;
+        }
+    }
+
+    pub fn set_violation(&mut self, kind: RegionKind, violation: Violation) {
+        for region in self.regions.iter_mut() {
+            if region.kind == kind {
+                region.violation = violation;
+            }
+        }
+    }
+
+    pub fn region(&self, address: u16) -> &Region {
+        &self.regions[self.lookup[address as usize] as usize]
+    }
+
+    // Follows mirrors down to the region that actually backs the address.
+    pub fn resolve(&self, address: u16) -> (u16, &Region) {
+        let mut address = address;
+
+        for _ in 0..self.regions.len() {
+            let region = self.region(address);
+
+            match region.kind {
+                RegionKind::Mirror(target) => address = target.wrapping_add(address - region.start),
+                _ => return (address, region),
+            }
+        }
+
+        panic!("Memory mirror loop at {:#06x}", address);
+    }
+
+    pub fn target(&self, address: u16) -> Option<Target> {
+        let (physical, region) = self.resolve(address);
+
+        match region.kind {
+            RegionKind::Device(index) => Some(Target::Device(index, physical - region.start)),
+            RegionKind::Unmapped => None,
+            _ => Some(Target::Memory(physical as usize)),
+        }
+    }
+
+    // An error means the region's policy is to stop the machine.
+    pub fn read(&self, address: u16) -> Result<Option<Target>, String> {
+        let target = self.target(address);
+
+        if target.is_none() {
+            let region = self.resolve(address).1;
+            violation(region.violation, format!("Read from unmapped memory at {:#06x}", address))?;
+        }
+
+        Ok(target)
+    }
+
+    pub fn write(&self, address: u16, value: u8) -> Result<Option<Target>, String> {
+        let (_, region) = self.resolve(address);
+
+        match region.kind {
+            RegionKind::Rom => {
+                violation(region.violation, format!("Write of {:#04x} to ROM at {:#06x}", value, address))?;
+                Ok(None)
+            },
+            RegionKind::Unmapped => {
+                violation(region.violation, format!("Write of {:#04x} to unmapped memory at {:#06x}", value, address))?;
+                Ok(None)
+            },
+            _ => Ok(self.target(address)),
+        }
+    }
+}
+
+pub fn violation(violation: Violation, message: String) -> Result<(), String> {
+    match violation {
+        Violation::Ignore => (),
+        Violation::Log => println!("{}", message),
+        Violation::Stop => return Err(message),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ram::{Sram, OPEN_BUS};
+
+    #[test]
+    fn later_regions_take_precedence() {
+        let mut map = MemoryMap::flat();
+        map.add(0x1000, 0x1fff, RegionKind::Rom);
+
+        assert_eq!(map.region(0x0fff).kind, RegionKind::Ram);
+        assert_eq!(map.region(0x1000).kind, RegionKind::Rom);
+        assert_eq!(map.region(0x1fff).kind, RegionKind::Rom);
+        assert_eq!(map.region(0x2000).kind, RegionKind::Ram);
+    }
+
+    #[test]
+    fn mirrors_resolve_to_the_backing_region() {
+        let map = MemoryMap::invaders();
+
+        assert_eq!(map.target(0x4000), Some(Target::Memory(0x2000)));
+        assert_eq!(map.target(0x5fff), Some(Target::Memory(0x3fff)));
+        assert_eq!(map.write(0x4123, 0), Ok(Some(Target::Memory(0x2123))));
+        assert_eq!(map.target(0x6000), None);
+    }
+
+    #[test]
+    fn devices_get_offsets_into_their_region() {
+        let mut map = MemoryMap::invaders();
+        map.add(0xc000, 0xdfff, RegionKind::Device(0));
+
+        assert_eq!(map.target(0xc000), Some(Target::Device(0, 0)));
+        assert_eq!(map.target(0xc405), Some(Target::Device(0, 0x405)));
+    }
+
+    #[test]
+    fn ignored_and_logged_violations_drop_the_access() {
+        let mut map = MemoryMap::invaders();
+
+        assert_eq!(map.write(0x0000, 0xff), Ok(None));
+        assert_eq!(map.read(0x8000), Ok(None));
+
+        map.set_violation(RegionKind::Rom, Violation::Log);
+        map.set_violation(RegionKind::Unmapped, Violation::Log);
+
+        assert_eq!(map.write(0x0000, 0xff), Ok(None));
+        assert_eq!(map.write(0x8000, 0xff), Ok(None));
+        assert_eq!(map.read(0x0000), Ok(Some(Target::Memory(0))));
+    }
+
+    #[test]
+    fn stop_on_rom_write() {
+        let mut map = MemoryMap::invaders();
+        map.set_violation(RegionKind::Rom, Violation::Stop);
+
+        assert_eq!(map.write(0x0010, 0xff), Err(String::from("Write of 0xff to ROM at 0x0010")));
+    }
+
+    #[test]
+    fn stop_on_unmapped_read() {
+        let mut map = MemoryMap::invaders();
+        map.set_violation(RegionKind::Unmapped, Violation::Stop);
+
+        assert_eq!(map.read(0x8000), Err(String::from("Read from unmapped memory at 0x8000")));
+    }
+
+    #[test]
+    fn peeks_of_unmapped_memory_match_reads() {
+        let mut ram = Sram::new();
+        ram.map = MemoryMap::invaders();
+        ram.load_bytes(&[0x42; 4], 0x6000);
+        ram.load_bytes(&[0x24], 0x2000);
+
+        assert_eq!(ram.peek_byte(0x6000), ram.read_byte(0x6000));
+        assert_eq!(ram.peek_byte(0x6000), OPEN_BUS);
+        assert_eq!(ram.dump(0x5fff, 0x6001), vec![0x00, OPEN_BUS, OPEN_BUS]);
+        assert_eq!(ram.peek_byte(0x4000), 0x24);
+    }
+
+    #[test]
+    fn violation_names() {
+        assert_eq!(Violation::parse("stop"), Some(Violation::Stop));
+        assert_eq!(Violation::parse("panic"), None);
+    }
+}
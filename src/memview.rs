@@ -0,0 +1,229 @@
+use minifb::{MouseMode, Window, WindowOptions};
+
+use coverage::{EXECUTED, OPERAND, READ, WRITTEN};
+use ram::{Sram, RAM_SIZE};
+
+const CELL: usize = 2;
+const GRID_SIZE: usize = 256 * CELL;
+const HEX_WIDTH: usize = 384;
+
+const WIDTH: usize = GRID_SIZE + HEX_WIDTH;
+const HEIGHT: usize = GRID_SIZE;
+
+const FADE: u8 = 6;
+const MAX_ZOOM: usize = 3;
+const BYTES_PER_ROW: usize = 8;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+// 3x5 glyphs for 0-9, A-F and ':'.
+const FONT: [[u8; 5]; 17] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b111, 0b100, 0b111],
+    [0b111, 0b100, 0b111, 0b100, 0b100],
+    [0b000, 0b010, 0b000, 0b010, 0b000],
+];
+const COLON: usize = 16;
+
+// Recent access intensity per address, decaying a little every frame.
+pub struct Activity {
+    pub reads: Vec<u8>,
+    pub writes: Vec<u8>,
+    pub executes: Vec<u8>,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity {
+            reads: vec![0; RAM_SIZE],
+            writes: vec![0; RAM_SIZE],
+            executes: vec![0; RAM_SIZE],
+        }
+    }
+
+    pub fn touch(&mut self, address: u16, flag: u8) {
+        let address = address as usize;
+
+        if flag & (EXECUTED | OPERAND) != 0 {
+            self.executes[address] = 255;
+        } else if flag & WRITTEN != 0 {
+            self.writes[address] = 255;
+        } else if flag & READ != 0 {
+            self.reads[address] = 255;
+        }
+    }
+
+    fn fade(&mut self) {
+        for channel in [&mut self.reads, &mut self.writes, &mut self.executes].iter_mut() {
+            for value in channel.iter_mut() {
+                *value = value.saturating_sub(FADE);
+            }
+        }
+    }
+
+    fn color(&self, address: usize, value: u8) -> u32 {
+        let base = if value != 0 { 0x30 } else { 0x00 };
+
+        let r = self.writes[address].max(base) as u32;
+        let g = self.executes[address].max(base) as u32;
+        let b = self.reads[address].max(base) as u32;
+
+        (r << 16) | (g << 8) | b
+    }
+}
+
+// Second window showing all of memory as a 256x256 grid, with red for writes,
+// green for executes and blue for reads. The hex dump on the right follows the
+// mouse and can be zoomed with the scroll wheel.
+pub struct MemoryViewer {
+    window: Window,
+    buffer: Vec<u32>,
+    selected: u16,
+    zoom: usize,
+}
+
+impl MemoryViewer {
+    pub fn new() -> MemoryViewer {
+        let window = Window::new("Memory", WIDTH, HEIGHT, WindowOptions::default()).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+
+        MemoryViewer {
+            window,
+            buffer: vec![0; WIDTH * HEIGHT],
+            selected: 0x2000,
+            zoom: 2,
+        }
+    }
+
+    pub fn update(&mut self, ram: &mut Sram) {
+        if !self.window.is_open() {
+            return;
+        }
+
+        if let Some((x, y)) = self.window.get_mouse_pos(MouseMode::Discard) {
+            let (x, y) = (x as usize, y as usize);
+
+            if x < GRID_SIZE && y < GRID_SIZE {
+                self.selected = ((y / CELL) * 256 + x / CELL) as u16;
+            }
+        }
+
+        if let Some((_, scroll)) = self.window.get_scroll_wheel() {
+            if scroll > 0.0 && self.zoom < MAX_ZOOM {
+                self.zoom += 1;
+            } else if scroll < 0.0 && self.zoom > 1 {
+                self.zoom -= 1;
+            }
+        }
+
+        for pixel in self.buffer.iter_mut() {
+            *pixel = 0;
+        }
+
+        // Values are peeked through the memory map, so mirrors and devices
+        // show what the CPU would read.
+        if let Some(mut activity) = ram.activity.take() {
+            self.draw_grid(&activity, ram);
+            self.draw_hex(&activity, ram);
+            activity.fade();
+            ram.activity = Some(activity);
+        }
+
+        self.window.update_with_buffer(&self.buffer).unwrap();
+    }
+
+    fn draw_grid(&mut self, activity: &Activity, ram: &Sram) {
+        for address in 0..RAM_SIZE {
+            let value = ram.peek_byte(address as u16);
+
+            let color = if address == self.selected as usize {
+                0xffffff
+            } else {
+                activity.color(address, value)
+            };
+
+            let x = (address & 0xff) * CELL;
+            let y = (address >> 8) * CELL;
+
+            for dy in 0..CELL {
+                for dx in 0..CELL {
+                    self.buffer[(y + dy) * WIDTH + x + dx] = color;
+                }
+            }
+        }
+    }
+
+    fn draw_hex(&mut self, activity: &Activity, ram: &Sram) {
+        let char_width = (GLYPH_WIDTH + 1) * self.zoom;
+        let line_height = (GLYPH_HEIGHT + 2) * self.zoom;
+        let rows = HEIGHT / line_height;
+
+        let selected_row = self.selected as usize / BYTES_PER_ROW;
+        let first_row = selected_row.saturating_sub(rows / 2).min(RAM_SIZE / BYTES_PER_ROW - rows);
+
+        for row in 0..rows {
+            let address = (first_row + row) * BYTES_PER_ROW;
+            let y = row * line_height + self.zoom;
+            let mut x = GRID_SIZE + 2 * char_width;
+
+            for shift in [12, 8, 4, 0].iter() {
+                self.draw_glyph(x, y, (address >> shift) & 0xf, 0x808080);
+                x += char_width;
+            }
+
+            self.draw_glyph(x, y, COLON, 0x808080);
+            x += 2 * char_width;
+
+            for offset in 0..BYTES_PER_ROW {
+                let address = address + offset;
+                let value = ram.peek_byte(address as u16);
+
+                let color = if address == self.selected as usize {
+                    0xffffff
+                } else {
+                    activity.color(address, value) | 0x808080
+                };
+
+                self.draw_glyph(x, y, (value >> 4) as usize, color);
+                self.draw_glyph(x + char_width, y, (value & 0xf) as usize, color);
+                x += 3 * char_width;
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, x: usize, y: usize, glyph: usize, color: u32) {
+        for (row, bits) in FONT[glyph].iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+
+                for dy in 0..self.zoom {
+                    for dx in 0..self.zoom {
+                        let px = x + column * self.zoom + dx;
+                        let py = y + row * self.zoom + dy;
+
+                        if px < WIDTH && py < HEIGHT {
+                            self.buffer[py * WIDTH + px] = color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use ram::Sram;
 
 pub struct Opcode {
       pub opcode: u8,
@@ -12,9 +13,30 @@ impl Opcode {
       }
 }
 
-impl fmt::Debug for Opcode {
-      fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op = match self.opcode {
+impl Opcode {
+      pub fn length(&self) -> u16 {
+            match self.opcode {
+                  0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2a | 0x32 | 0x3a |
+                  0xc2 | 0xc3 | 0xc4 | 0xca | 0xcb | 0xcc | 0xcd | 0xd2 |
+                  0xd4 | 0xda | 0xdc | 0xdd | 0xe2 | 0xe4 | 0xea | 0xec |
+                  0xed | 0xf2 | 0xf4 | 0xfa | 0xfc | 0xfd                 => 3,
+                  0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e |
+                  0xc6 | 0xce | 0xd3 | 0xd6 | 0xdb | 0xde | 0xe6 | 0xee |
+                  0xf6 | 0xfe                                             => 2,
+                  _                                                       => 1,
+            }
+      }
+
+      pub fn disassemble(&self, lo: u8, hi: u8) -> String {
+            match self.length() {
+                  3 => format!("{} ${:02x}{:02x}", self.mnemonic(), hi, lo),
+                  2 => format!("{} #${:02x}", self.mnemonic(), lo),
+                  _ => String::from(self.mnemonic()),
+            }
+      }
+
+      pub fn mnemonic(&self) -> &'static str {
+        match self.opcode {
             0x00 => "NOP",
             0x01 => "LXI B",
             0x02 => "STAX B",
@@ -272,7 +294,17 @@ impl fmt::Debug for Opcode {
             0xfe => "CPI",
             0xff => "RST 7",
             _ => unreachable!(),
-        };  
-        write!(f, "{:#04x} ({})", self.opcode, op)
+        }
       }
-}
\ No newline at end of file
+}
+
+impl fmt::Debug for Opcode {
+      fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#04x} ({})", self.opcode, self.mnemonic())
+      }
+}
+pub fn disassemble(ram: &Sram, address: u16) -> String {
+      let opcode = Opcode::new(ram.peek_byte(address));
+
+      opcode.disassemble(ram.peek_byte(address.wrapping_add(1)), ram.peek_byte(address.wrapping_add(2)))
+}
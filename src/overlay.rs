@@ -0,0 +1,156 @@
+// Colored gel strips stuck on the monitor. Coordinates are in the displayed,
+// already rotated, image.
+
+pub const MONOCHROME: &str = "mono";
+
+pub const WHITE: u32 = 0xffffff;
+pub const BLACK: u32 = 0x000000;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub color: String,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Overlay {
+    // Shows through where the screen is dark, like a lit backdrop.
+    pub background: Option<String>,
+    #[serde(default, rename = "rect")]
+    pub rects: Vec<Rect>,
+}
+
+impl Overlay {
+    pub fn validate(&self) -> Result<(), String> {
+        for color in self.background.iter().chain(self.rects.iter().map(|rect| &rect.color)) {
+            parse_color(color)?;
+        }
+
+        Ok(())
+    }
+}
+
+// An overlay resolved to a color for every pixel.
+pub struct Tint {
+    pub lit: Vec<u32>,
+    pub background: u32,
+}
+
+impl Tint {
+    pub fn monochrome(width: usize, height: usize) -> Tint {
+        Tint {
+            lit: vec![WHITE; width * height],
+            background: BLACK,
+        }
+    }
+
+    // Later rects are stuck on top of earlier ones.
+    pub fn new(overlay: &Overlay, width: usize, height: usize) -> Tint {
+        let mut tint = Tint::monochrome(width, height);
+
+        if let Some(ref color) = overlay.background {
+            tint.background = parse_color(color).unwrap();
+        }
+
+        for rect in overlay.rects.iter() {
+            let color = parse_color(&rect.color).unwrap();
+
+            for y in rect.y..(rect.y + rect.height).min(height) {
+                for x in rect.x..(rect.x + rect.width).min(width) {
+                    tint.lit[y * width + x] = color;
+                }
+            }
+        }
+
+        tint
+    }
+
+    // Gels filter whatever the screen shows, dark pixels show the background.
+    pub fn apply(&self, pixels: &[u32]) -> Vec<u32> {
+        pixels.iter().zip(self.lit.iter())
+            .map(|(&pixel, &color)| if pixel == BLACK { self.background } else { filter(pixel, color) })
+            .collect()
+    }
+}
+
+fn filter(pixel: u32, gel: u32) -> u32 {
+    (0..3).map(|channel| channel * 8).fold(0, |rgb, shift| {
+        let level = ((pixel >> shift) & 0xff) * ((gel >> shift) & 0xff) / 0xff;
+        rgb | level << shift
+    })
+}
+
+// "rrggbb" to the 0RGB pixels minifb expects.
+pub fn parse_color(color: &str) -> Result<u32, String> {
+    match u32::from_str_radix(color, 16) {
+        Ok(value) if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) => Ok(value),
+        _ => Err(format!("Invalid color {:?}, expected rrggbb", color)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: usize, y: usize, width: usize, height: usize, color: &str) -> Rect {
+        Rect { x, y, width, height, color: String::from(color) }
+    }
+
+    #[test]
+    fn rects_are_clipped_and_stacked() {
+        let overlay = Overlay {
+            background: Some(String::from("101040")),
+            rects: vec![
+                rect(0, 1, 4, 2, "ff0000"),
+                // Hangs off the right and bottom edges, over the first rect.
+                rect(2, 2, 10, 10, "00ff00"),
+            ],
+        };
+
+        let tint = Tint::new(&overlay, 4, 4);
+        let red = 0xff0000;
+        let green = 0x00ff00;
+
+        assert_eq!(tint.background, 0x101040);
+        assert_eq!(tint.lit, vec![
+            WHITE, WHITE, WHITE, WHITE,
+            red, red, red, red,
+            red, red, green, green,
+            WHITE, WHITE, green, green,
+        ]);
+    }
+
+    #[test]
+    fn rects_off_screen_are_ignored() {
+        let tint = Tint::new(&Overlay { background: None, rects: vec![rect(8, 8, 2, 2, "ff0000")] }, 4, 4);
+
+        assert!(tint.lit.iter().all(|&color| color == WHITE));
+        assert_eq!(tint.background, BLACK);
+    }
+
+    #[test]
+    fn apply_filters_lit_pixels_and_fills_dark_ones() {
+        let tint = Tint {
+            lit: vec![0xff8000, 0xff8000, WHITE],
+            background: 0x101040,
+        };
+
+        assert_eq!(tint.apply(&[WHITE, BLACK, 0x808080]), vec![0xff8000, 0x101040, 0x808080]);
+        assert_eq!(tint.apply(&[0x808080, 0x000000, 0x000000]), vec![0x804000, 0x101040, 0x101040]);
+    }
+
+    #[test]
+    fn colors_are_six_hex_digits() {
+        assert_eq!(parse_color("20ff20"), Ok(0x20ff20));
+        assert_eq!(parse_color("20FF20"), Ok(0x20ff20));
+        assert!(parse_color("fff").is_err());
+        assert!(parse_color("+fffff").is_err());
+        assert!(parse_color("0x20ff20").is_err());
+        assert!(parse_color("20ff2g").is_err());
+    }
+}
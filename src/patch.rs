@@ -0,0 +1,332 @@
+use crc32fast;
+
+// IPS and BPS patches applied to a whole ROM image.
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const IPS_MAX_RECORD: usize = 0xffff;
+// A record costs 5 bytes, so shorter runs of unchanged bytes are cheaper to
+// include than to start a new record for.
+const IPS_MERGE_GAP: usize = 5;
+
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+pub fn apply(image: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(image, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(image, patch)
+    } else {
+        Err(String::from("not an IPS or BPS patch"))
+    }
+}
+
+pub fn apply_ips(image: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader::new(patch);
+    let mut target = image.to_vec();
+
+    if reader.bytes(IPS_MAGIC.len())? != IPS_MAGIC {
+        return Err(String::from("not an IPS patch"));
+    }
+
+    loop {
+        let header = reader.bytes(3)?;
+
+        if header == IPS_EOF {
+            break;
+        }
+
+        let offset = reader.number(header);
+        let size = reader.be(2)?;
+
+        let data = if size == 0 {
+            let count = reader.be(2)?;
+            vec![reader.byte()?; count]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Optional truncation extension.
+    if reader.remaining() == 3 {
+        let size = reader.be(3)?;
+        target.truncate(size);
+    }
+
+    Ok(target)
+}
+
+pub fn create_ips(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = IPS_MAGIC.to_vec();
+    let differs = |offset: usize| offset >= source.len() || source[offset] != target[offset];
+    let mut offset = 0;
+
+    while offset < target.len() {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+
+        // An offset spelling "EOF" would end the patch, start a byte earlier.
+        let start = if offset == 0x454f46 { offset - 1 } else { offset };
+        let mut end = offset;
+        let mut gap = 0;
+
+        while end < target.len() && end - start < IPS_MAX_RECORD && gap <= IPS_MERGE_GAP {
+            gap = if differs(end) { 0 } else { gap + 1 };
+            end += 1;
+        }
+
+        end -= gap;
+        let data = &target[start..end];
+
+        patch.extend_from_slice(&[(start >> 16) as u8, (start >> 8) as u8, start as u8]);
+
+        if data.len() > 3 && data.iter().all(|&byte| byte == data[0]) {
+            patch.extend_from_slice(&[0, 0, (data.len() >> 8) as u8, data.len() as u8, data[0]]);
+        } else {
+            patch.extend_from_slice(&[(data.len() >> 8) as u8, data.len() as u8]);
+            patch.extend_from_slice(data);
+        }
+
+        offset = end;
+    }
+
+    patch.extend_from_slice(IPS_EOF);
+
+    if target.len() < source.len() {
+        let size = target.len();
+        patch.extend_from_slice(&[(size >> 16) as u8, (size >> 8) as u8, size as u8]);
+    }
+
+    patch
+}
+
+pub fn apply_bps(image: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_MAGIC.len() + 12 || !patch.starts_with(BPS_MAGIC) {
+        return Err(String::from("not a BPS patch"));
+    }
+
+    let footer = patch.len() - 12;
+    let crc = |index: usize| u32::from(patch[index]) | u32::from(patch[index + 1]) << 8 | u32::from(patch[index + 2]) << 16 | u32::from(patch[index + 3]) << 24;
+    let (source_crc, target_crc, patch_crc) = (crc(footer), crc(footer + 4), crc(footer + 8));
+
+    if crc32fast::hash(&patch[..footer + 8]) != patch_crc {
+        return Err(String::from("patch file is corrupt, CRC32 mismatch"));
+    }
+
+    if crc32fast::hash(image) != source_crc {
+        return Err(format!("patch is for a ROM image with CRC32 {:08x}, this one is {:08x}", source_crc, crc32fast::hash(image)));
+    }
+
+    let mut reader = Reader::new(&patch[..footer]);
+    reader.bytes(BPS_MAGIC.len())?;
+
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != image.len() {
+        return Err(format!("patch is for a {:#x} byte image, this one is {:#x} bytes", source_size, image.len()));
+    }
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    while reader.remaining() > 0 {
+        let command = reader.varint()?;
+        let length = (command >> 2) + 1;
+        let output = target.len();
+
+        match command & 3 {
+            // SourceRead
+            0 => target.extend_from_slice(image.get(output..output + length).ok_or("source read out of range")?),
+            // TargetRead
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, reader.varint()?).ok_or("source copy out of range")?;
+                target.extend_from_slice(image.get(source_offset..source_offset + length).ok_or("source copy out of range")?);
+                source_offset += length;
+            },
+            // TargetCopy, may overlap the bytes it is writing
+            _ => {
+                target_offset = relative(target_offset, reader.varint()?).ok_or("target copy out of range")?;
+
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or("target copy out of range")?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(format!("patch produced {:#x} bytes, expected {:#x}", target.len(), target_size));
+    }
+
+    if crc32fast::hash(&target) != target_crc {
+        return Err(format!("patched image has CRC32 {:08x}, expected {:08x}", crc32fast::hash(&target), target_crc));
+    }
+
+    Ok(target)
+}
+
+fn relative(offset: usize, encoded: usize) -> Option<usize> {
+    let delta = encoded >> 1;
+
+    if encoded & 1 == 1 {
+        offset.checked_sub(delta)
+    } else {
+        offset.checked_add(delta)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.remaining() < count {
+            return Err(format!("patch ends early at offset {:#x}", self.position));
+        }
+
+        self.position += count;
+        Ok(&self.data[self.position - count..self.position])
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, count: usize) -> Result<usize, String> {
+        let bytes = self.bytes(count)?;
+        Ok(self.number(bytes))
+    }
+
+    fn number(&self, bytes: &[u8]) -> usize {
+        bytes.iter().fold(0, |number, &byte| number << 8 | byte as usize)
+    }
+
+    fn varint(&mut self) -> Result<usize, String> {
+        let mut data = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let byte = self.byte()?;
+            data = data.checked_add((byte & 0x7f) as usize * shift).ok_or("varint overflow")?;
+
+            if byte & 0x80 != 0 {
+                return Ok(data);
+            }
+
+            shift = shift.checked_shl(7).ok_or("varint overflow")?;
+            data = data.checked_add(shift).ok_or("varint overflow")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        varint(source.len(), &mut patch);
+        varint(target.len(), &mut patch);
+        varint(0, &mut patch);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let source: Vec<u8> = (0..0x200).map(|x| x as u8).collect();
+        let mut target = source.clone();
+        target[0x10] = 0xff;
+        target[0x13] = 0xfe;
+        for byte in target[0x100..0x140].iter_mut() {
+            *byte = 0xaa;
+        }
+        target.extend_from_slice(&[1, 2, 3]);
+
+        let patch = create_ips(&source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+        assert!(patch.len() < 40);
+    }
+
+    #[test]
+    fn ips_truncates() {
+        let source = vec![7; 0x100];
+        let target = vec![7; 0x80];
+
+        assert_eq!(apply_ips(&source, &create_ips(&source, &target)).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_applies_all_actions() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcdXYXYXYgh".to_vec();
+
+        let mut actions = Vec::new();
+        // SourceRead 4, TargetRead "XY", TargetCopy 4 from offset 4, SourceCopy 2 from offset 6.
+        varint(3 << 2, &mut actions);
+        varint(1 << 2 | 1, &mut actions);
+        actions.extend_from_slice(b"XY");
+        varint(3 << 2 | 3, &mut actions);
+        varint(4 << 1, &mut actions);
+        varint(1 << 2 | 2, &mut actions);
+        varint(6 << 1, &mut actions);
+
+        assert_eq!(apply(&source, &bps(&source, &target, &actions)).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_rejects_wrong_source() {
+        let source = b"abcd".to_vec();
+        let mut actions = Vec::new();
+        varint(3 << 2, &mut actions);
+        let patch = bps(&source, &source, &actions);
+
+        assert!(apply_bps(b"abce", &patch).unwrap_err().contains("CRC32"));
+    }
+}
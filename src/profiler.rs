@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+
+use opcode::{Opcode, disassemble};
+use ram::{Sram, RAM_SIZE};
+
+const REPORT_LINES: usize = 50;
+
+pub struct Profiler {
+    pub address_counts: Vec<u64>,
+    pub address_cycles: Vec<u64>,
+    pub opcode_counts: Vec<u64>,
+    pub opcode_cycles: Vec<u64>,
+    pub total_cycles: u64,
+
+    // Cycles per interrupt vector, including everything called from the handler.
+    pub interrupt_cycles: HashMap<u16, u64>,
+
+    // Cycles per (interrupt vector, address), used for the folded output.
+    folded: HashMap<(Option<u16>, u16), u64>,
+
+    // Active handlers as (vector, stack pointer after the return address was pushed).
+    interrupts: Vec<(u16, u16)>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            address_counts: vec![0; RAM_SIZE],
+            address_cycles: vec![0; RAM_SIZE],
+            opcode_counts: vec![0; 256],
+            opcode_cycles: vec![0; 256],
+            total_cycles: 0,
+
+            interrupt_cycles: HashMap::new(),
+            folded: HashMap::new(),
+            interrupts: Vec::new(),
+        }
+    }
+
+    pub fn enter_interrupt(&mut self, vector: u16, sp: u16) {
+        self.interrupts.push((vector, sp));
+    }
+
+    pub fn record(&mut self, pc: u16, opcode: u8, cycles: u64, sp: u16) {
+        self.address_counts[pc as usize] += 1;
+        self.address_cycles[pc as usize] += cycles;
+        self.opcode_counts[opcode as usize] += 1;
+        self.opcode_cycles[opcode as usize] += cycles;
+        self.total_cycles += cycles;
+
+        let vector = self.interrupts.last().map(|&(vector, _)| vector);
+
+        if let Some(vector) = vector {
+            *self.interrupt_cycles.entry(vector).or_insert(0) += cycles;
+        }
+
+        *self.folded.entry((vector, pc)).or_insert(0) += cycles;
+
+        // A handler is finished once its return address has been popped.
+        while let Some(&(_, handler_sp)) = self.interrupts.last() {
+            if sp <= handler_sp {
+                break;
+            }
+
+            self.interrupts.pop();
+        }
+    }
+
+    pub fn report(&self, ram: &Sram) -> String {
+        let mut out = String::new();
+        let total = self.total_cycles.max(1) as f64;
+
+        out.push_str(&format!("Total cycles: {}\n\n", self.total_cycles));
+
+        out.push_str("Interrupt handlers:\n");
+        let mut vectors: Vec<_> = self.interrupt_cycles.iter().collect();
+        vectors.sort();
+
+        for (vector, cycles) in vectors {
+            out.push_str(&format!("  Vector {:#04x}: {:>12} cycles {:6.2}%\n",
+                vector, cycles, *cycles as f64 * 100.0 / total));
+        }
+
+        out.push_str("\nHot spots:\n");
+        let mut addresses: Vec<usize> = (0..RAM_SIZE).filter(|&a| self.address_counts[a] > 0).collect();
+        addresses.sort_by(|&a, &b| self.address_cycles[b].cmp(&self.address_cycles[a]));
+
+        for &address in addresses.iter().take(REPORT_LINES) {
+            out.push_str(&format!("  {:#06x}: {:>12} cycles {:6.2}% {:>10} times  {}\n",
+                address,
+                self.address_cycles[address],
+                self.address_cycles[address] as f64 * 100.0 / total,
+                self.address_counts[address],
+                disassemble(ram, address as u16)));
+        }
+
+        out.push_str("\nOpcodes:\n");
+        let mut opcodes: Vec<usize> = (0..256).filter(|&o| self.opcode_counts[o] > 0).collect();
+        opcodes.sort_by(|&a, &b| self.opcode_counts[b].cmp(&self.opcode_counts[a]));
+
+        for &opcode in opcodes.iter() {
+            out.push_str(&format!("  {:<10} {:>12} times {:>12} cycles {:6.2}%\n",
+                Opcode::new(opcode as u8).mnemonic(),
+                self.opcode_counts[opcode],
+                self.opcode_cycles[opcode],
+                self.opcode_cycles[opcode] as f64 * 100.0 / total));
+        }
+
+        out
+    }
+
+    // One line per sampled stack in the folded format read by flamegraph.pl and friends.
+    // Spaces separate the count from the stack, so frames use underscores instead.
+    pub fn folded(&self, ram: &Sram) -> String {
+        let mut entries: Vec<_> = self.folded.iter().collect();
+        entries.sort();
+
+        let mut out = String::new();
+
+        for (&(vector, pc), cycles) in entries {
+            let context = match vector {
+                Some(vector) => format!("int_{:#04x}", vector),
+                None => String::from("main"),
+            };
+
+            out.push_str(&format!("{};{:#06x}_{} {}\n", context, pc, disassemble(ram, pc).replace(' ', "_"), cycles));
+        }
+
+        out
+    }
+
+    pub fn write(&self, ram: &Sram, file_name: &str) -> io::Result<()> {
+        File::create(file_name)?.write_all(self.report(ram).as_bytes())?;
+        File::create(format!("{}.folded", file_name))?.write_all(self.folded(ram).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_cycles_per_address_and_opcode() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x0000, 0x00, 4, 0x2400);
+        profiler.record(0x0001, 0x21, 10, 0x2400);
+        profiler.record(0x0000, 0x00, 4, 0x2400);
+
+        assert_eq!(profiler.address_counts[0x0000], 2);
+        assert_eq!(profiler.address_cycles[0x0000], 8);
+        assert_eq!(profiler.address_cycles[0x0001], 10);
+        assert_eq!(profiler.opcode_counts[0x00], 2);
+        assert_eq!(profiler.opcode_cycles[0x21], 10);
+        assert_eq!(profiler.total_cycles, 18);
+    }
+
+    #[test]
+    fn interrupts_last_until_their_return_address_is_popped() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x0100, 0x00, 4, 0x2400);
+
+        // RST 1 pushed the return address.
+        profiler.enter_interrupt(0x08, 0x23fe);
+        profiler.record(0x0008, 0xf5, 11, 0x23fc);
+        profiler.record(0x0009, 0xf1, 10, 0x23fe);
+        // The RET itself still belongs to the handler.
+        profiler.record(0x000a, 0xc9, 10, 0x2400);
+        profiler.record(0x0101, 0x00, 4, 0x2400);
+
+        assert_eq!(profiler.interrupt_cycles[&0x08], 31);
+        assert_eq!(profiler.total_cycles, 39);
+    }
+
+    #[test]
+    fn folded_frames_have_no_spaces() {
+        let mut ram = Sram::new();
+        ram.load_bytes(&[0x21, 0x00, 0x24], 0x0010);
+
+        let mut profiler = Profiler::new();
+        profiler.record(0x0010, 0x21, 10, 0x2400);
+        profiler.enter_interrupt(0x10, 0x23fe);
+        profiler.record(0x0010, 0x21, 10, 0x23fe);
+        profiler.record(0x0010, 0x21, 10, 0x23fe);
+
+        assert_eq!(profiler.folded(&ram), "main;0x0010_LXI_H_$2400 10\nint_0x10;0x0010_LXI_H_$2400 20\n");
+    }
+}
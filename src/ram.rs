@@ -1,11 +1,44 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use util::*;
+use hexfile::{self, Format};
+use coverage::{self, Coverage};
+use memview::Activity;
+use memmap::{MemoryMap, MemoryDevice, RegionKind, Target};
 
 pub const RAM_SIZE: usize = 64*1024;
+// What reads of unmapped memory return.
+pub const OPEN_BUS: u8 = 0x00;
+
+// An address range written to an Intel HEX or S-record file.
+pub struct MemoryDump {
+    pub path: String,
+    pub start: u16,
+    pub end: u16,
+    pub format: Format,
+}
+
+impl MemoryDump {
+    pub fn write(&self, ram: &Sram) -> io::Result<()> {
+        let name = Path::new(&self.path).file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        let text = hexfile::write(self.format, self.start, &ram.dump(self.start, self.end), &name);
+
+        File::create(&self.path)?.write_all(text.as_bytes())
+    }
+}
 
 pub struct Sram {
     pub bytes: Vec<u8>,
+    pub map: MemoryMap,
+    pub devices: Vec<Box<dyn MemoryDevice>>,
+    // Current CPU cycle, passed on to devices.
+    pub cycle: u64,
+    pub coverage: Option<Coverage>,
+    pub activity: Option<Activity>,
+    // Set by an access whose region policy is to stop the machine.
+    pub stop: Option<String>,
 }
 
 impl Sram {
@@ -14,46 +47,229 @@ impl Sram {
         
         Sram {
             bytes: bytes, 
+            map: MemoryMap::flat(),
+            devices: Vec::new(),
+            cycle: 0,
+            coverage: None,
+            activity: None,
+            stop: None,
         }
     }
 
     pub fn load_offset(&mut self, file_name: &str, offset: u16) {
-        let mut f = File::open(&file_name).expect("Unable to open file");
+        let mut f = File::open(&file_name).unwrap_or_else(|e| panic!("Unable to open {}: {}", file_name, e));
         
         let mut rom_bytes: Vec<u8> = Vec::new();
-        f.read_to_end(&mut rom_bytes).expect("Unable to read bytes");
+        f.read_to_end(&mut rom_bytes).unwrap_or_else(|e| panic!("Unable to read {}: {}", file_name, e));
+
+        self.load_bytes(&rom_bytes, offset);
+    }
 
-        for (i, &item) in rom_bytes.iter().enumerate() {
+    // Bypasses the memory map so ROM regions can be filled.
+    pub fn load_bytes(&mut self, bytes: &[u8], offset: u16) {
+        for (i, &item) in bytes.iter().enumerate() {
             self.bytes[i+offset as usize] = item;
         }
     }
 
-    pub fn load(&mut self, file_name: &str)
-    {
-        self.load_offset(file_name, 0x00);
+    // Intel HEX or S-record file, loaded at the addresses it gives. Returns the
+    // start address if the file has one.
+    pub fn load_hex(&mut self, file_name: &str) -> Result<Option<u16>, String> {
+        let mut text = String::new();
+        File::open(file_name).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| e.to_string())?;
+
+        let image = hexfile::parse(&text).map_err(|e| e.to_string())?;
+
+        for (address, data) in image.chunks.iter() {
+            self.load_bytes(data, *address);
+        }
+
+        Ok(image.start)
     }
 
-    pub fn read_byte(&self, address: u16) -> u8 {
-        return self.bytes[address as usize];
+    // Copy of an inclusive range as the CPU sees it.
+    pub fn dump(&self, start: u16, end: u16) -> Vec<u8> {
+        (start..=end).map(|address| self.peek_byte(address)).collect()
     }
 
-    pub fn read_dword(&self, address: u16) -> u16 {        
-        return u8_to_u16(self.bytes[address as usize], self.bytes[address as usize + 1]);
+    // Maps a device over the given range. Must be called after the memory map is set up.
+    pub fn attach(&mut self, start: u16, end: u16, device: Box<dyn MemoryDevice>) {
+        let index = self.devices.len();
+
+        self.devices.push(device);
+        self.map.add(start, end, RegionKind::Device(index));
+    }
+
+    // Memory itself keeps its contents over a reset, only devices are reset.
+    pub fn reset(&mut self, cycle: u64) {
+        for device in self.devices.iter_mut() {
+            device.reset(cycle);
+        }
+    }
+
+    // The backing bytes, then the devices in the order they were attached.
+    pub fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.bytes)?;
+        out.write_u8(self.devices.len() as u8)?;
+
+        for device in self.devices.iter() {
+            device.save(out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        let mut bytes = vec![0; RAM_SIZE];
+        input.read_exact(&mut bytes)?;
+
+        if input.read_u8()? as usize != self.devices.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Save state is for a different set of memory devices"));
+        }
+
+        self.bytes = bytes;
+
+        for device in self.devices.iter_mut() {
+            device.load(input, self.cycle)?;
+        }
+
+        Ok(())
+    }
+
+    // Reads without any side effects, for debuggers and disassembly.
+    pub fn peek_byte(&self, address: u16) -> u8 {
+        match self.map.target(address) {
+            Some(Target::Memory(physical)) => self.bytes[physical],
+            Some(Target::Device(index, offset)) => self.devices[index].peek(offset),
+            None => OPEN_BUS,
+        }
+    }
+
+    pub fn fetch_opcode(&mut self, address: u16) -> u8 {
+        self.mark(address, coverage::EXECUTED);
+        self.load_byte(address)
+    }
+
+    pub fn fetch_operand(&mut self, address: u16) -> u8 {
+        self.mark(address, coverage::OPERAND);
+        self.load_byte(address)
+    }
+
+    pub fn read_byte(&mut self, address: u16) -> u8 {
+        self.mark(address, coverage::READ);
+        self.load_byte(address)
+    }
+
+    pub fn read_dword(&mut self, address: u16) -> u16 {        
+        u8_to_u16(self.read_byte(address), self.read_byte(address.wrapping_add(1)))
     }
 
     pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.bytes[address as usize] = value;
+        self.mark(address, coverage::WRITTEN);
+
+        match self.map.write(address, value) {
+            Ok(Some(Target::Memory(physical))) => self.bytes[physical] = value,
+            Ok(Some(Target::Device(index, offset))) => self.devices[index].write(offset, value, self.cycle),
+            Ok(None) => (),
+            Err(message) => self.stop = Some(message),
+        }
     }
 
     pub fn write_dword(&mut self, address: u16, value: u16) {
         let (upper, lower) = u16_to_u8(value);
-        self.bytes[address as usize] = lower;
-        self.bytes[address as usize + 1] = upper;
+        self.write_byte(address, lower);
+        self.write_byte(address.wrapping_add(1), upper);
     }
 
     pub fn write_dword_stack(&mut self, address: u16, value: u16) {
         let (lower, upper) = u16_to_u8(value);
-        self.bytes[address as usize] = upper;
-        self.bytes[address as usize + 1] = lower;
+        self.write_byte(address, upper);
+        self.write_byte(address.wrapping_add(1), lower);
+    }
+
+    fn load_byte(&mut self, address: u16) -> u8 {
+        match self.map.read(address) {
+            Ok(Some(Target::Memory(physical))) => self.bytes[physical],
+            Ok(Some(Target::Device(index, offset))) => self.devices[index].read(offset, self.cycle),
+            Ok(None) => OPEN_BUS,
+            Err(message) => {
+                self.stop = Some(message);
+                OPEN_BUS
+            },
+        }
+    }
+
+    fn mark(&mut self, address: u16, flag: u8) {
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.mark(address, flag);
+        }
+
+        if let Some(ref mut activity) = self.activity {
+            activity.touch(address, flag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Counts reads so side effects can be observed.
+    struct Latch {
+        value: u8,
+        reads: u32,
+    }
+
+    impl MemoryDevice for Latch {
+        fn read(&mut self, _offset: u16, _cycle: u64) -> u8 {
+            self.reads += 1;
+            self.value
+        }
+
+        fn write(&mut self, offset: u16, value: u8, _cycle: u64) {
+            self.value = value.wrapping_add(offset as u8);
+        }
+
+        fn peek(&self, _offset: u16) -> u8 {
+            self.value
+        }
+    }
+
+    fn ram_with_latch() -> (Sram, Rc<RefCell<Latch>>) {
+        let latch = Rc::new(RefCell::new(Latch { value: 0, reads: 0 }));
+        let mut ram = Sram::new();
+        ram.map = MemoryMap::invaders();
+        ram.attach(0x8000, 0x80ff, Box::new(latch.clone()));
+
+        (ram, latch)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn attached_devices_receive_reads_and_writes() {
+        let (mut ram, latch) = ram_with_latch();
+
+        ram.write_byte(0x8002, 0x40);
+        assert_eq!(latch.borrow().value, 0x42);
+        assert_eq!(ram.bytes[0x8002], 0x00);
+
+        assert_eq!(ram.read_byte(0x80ff), 0x42);
+        assert_eq!(latch.borrow().reads, 1);
+
+        // Outside the device the map still applies.
+        ram.write_byte(0x2000, 0x55);
+        assert_eq!(ram.read_byte(0x2000), 0x55);
+        assert_eq!(latch.borrow().reads, 1);
+    }
+
+    #[test]
+    fn peek_bypasses_device_reads() {
+        let (mut ram, latch) = ram_with_latch();
+        ram.write_byte(0x8000, 0x07);
+
+        assert_eq!(ram.peek_byte(0x8000), 0x07);
+        assert_eq!(ram.dump(0x80fe, 0x8100), vec![0x07, 0x07, 0x00]);
+        assert_eq!(latch.borrow().reads, 0);
+    }
+}
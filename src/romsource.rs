@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use zip::ZipArchive;
+
+use manifest::Manifest;
+
+// Where the ROM chunks of a set are read from: loose files in a directory or
+// the members of a zip archive.
+pub enum RomSource {
+    Dir(PathBuf),
+    Zip(PathBuf, ZipArchive<File>),
+}
+
+impl RomSource {
+    // `path` may be a zip file, or a directory holding either the loose files
+    // or a MAME style <name>.zip.
+    pub fn open(path: &str, manifest: &Manifest) -> io::Result<RomSource> {
+        let path = Path::new(path);
+
+        if path.is_file() {
+            return RomSource::open_zip(path);
+        }
+
+        let zip_path = path.join(format!("{}.zip", manifest.name));
+        let loose = manifest.roms.iter().any(|rom| path.join(&rom.file).exists());
+
+        if !loose && zip_path.is_file() {
+            return RomSource::open_zip(&zip_path);
+        }
+
+        Ok(RomSource::Dir(path.to_path_buf()))
+    }
+
+    fn open_zip(path: &Path) -> io::Result<RomSource> {
+        let archive = ZipArchive::new(File::open(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(RomSource::Zip(path.to_path_buf(), archive))
+    }
+
+    pub fn name(&self, file: &str) -> String {
+        match *self {
+            RomSource::Dir(ref dir) => dir.join(file).display().to_string(),
+            RomSource::Zip(ref path, _) => format!("{}:{}", path.display(), file),
+        }
+    }
+
+    // Zip members are matched by file name, ignoring case and folders. The zip
+    // reader checks them against the CRC32 stored in the archive.
+    pub fn read(&mut self, file: &str) -> io::Result<Vec<u8>> {
+        match *self {
+            RomSource::Dir(ref dir) => {
+                let mut bytes = Vec::new();
+                File::open(dir.join(file))?.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            },
+            RomSource::Zip(_, ref mut archive) => {
+                let index = (0..archive.len())
+                    .find(|&index| archive.by_index(index).is_ok_and(|member| matches(member.name(), file)))
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", file)))?;
+
+                let mut member = archive.by_index(index).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let mut bytes = Vec::new();
+                member.read_to_end(&mut bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                    format!("{} does not match the CRC32 in the archive: {}", file, e)))?;
+
+                Ok(bytes)
+            },
+        }
+    }
+}
+
+fn matches(member: &str, file: &str) -> bool {
+    let name = member.rsplit('/').next().unwrap_or(member);
+    name.eq_ignore_ascii_case(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::{Cursor, Write};
+    use zip::{CompressionMethod, ZipWriter};
+    use zip::write::FileOptions;
+
+    fn zip(members: &[(&str, CompressionMethod, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for &(name, method, data) in members {
+            writer.start_file(name, FileOptions::default().compression_method(method)).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    // Writes the archive to a file of its own and opens it for the invaders set.
+    fn open(name: &str, archive: &[u8]) -> (RomSource, PathBuf) {
+        let path = env::temp_dir().join(format!("r8080-{}-{}.zip", std::process::id(), name));
+        fs::write(&path, archive).unwrap();
+
+        let source = RomSource::open(path.to_str().unwrap(), &Manifest::find("invaders").unwrap()).unwrap();
+        (source, path)
+    }
+
+    #[test]
+    fn stored_and_deflated_members_are_read() {
+        let stored: Vec<u8> = (0..=255).collect();
+        let deflated = vec![0x55; 0x800];
+        let (mut source, path) = open("methods", &zip(&[
+            ("invaders.h", CompressionMethod::Stored, &stored),
+            ("invaders.g", CompressionMethod::Deflated, &deflated),
+        ]));
+
+        assert_eq!(source.read("invaders.h").unwrap(), stored);
+        assert_eq!(source.read("invaders.g").unwrap(), deflated);
+        assert_eq!(source.name("invaders.g"), format!("{}:invaders.g", path.display()));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn members_match_by_base_name_ignoring_case() {
+        let (mut source, path) = open("names", &zip(&[
+            ("roms/INVADERS.H", CompressionMethod::Stored, b"h"),
+            ("invaders.hx", CompressionMethod::Stored, b"x"),
+        ]));
+
+        assert_eq!(source.read("invaders.h").unwrap(), b"h");
+        assert_eq!(source.read("Invaders.Hx").unwrap(), b"x");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_members_are_not_found() {
+        let (mut source, path) = open("missing", &zip(&[("invaders.h", CompressionMethod::Stored, b"h")]));
+        let error = source.read("invaders.e").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("invaders.e"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_members_fail_the_crc_check() {
+        let data = b"SPACE INVADERS ROM";
+        let mut archive = zip(&[("invaders.h", CompressionMethod::Stored, data)]);
+
+        // Damage the stored data but not the CRC32 recorded for it.
+        let at = archive.windows(data.len()).position(|window| window == data).unwrap();
+        archive[at] ^= 0xff;
+
+        let (mut source, path) = open("corrupt", &archive);
+        let error = source.read("invaders.h").unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("CRC32"), "{}", error);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn directories_fall_back_to_the_sets_zip() {
+        let dir = env::temp_dir().join(format!("r8080-{}-zipdir", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("invaders.zip"), zip(&[("invaders.h", CompressionMethod::Deflated, b"zipped")])).unwrap();
+
+        let manifest = Manifest::find("invaders").unwrap();
+        let mut source = RomSource::open(dir.to_str().unwrap(), &manifest).unwrap();
+        assert_eq!(source.read("invaders.h").unwrap(), b"zipped");
+
+        // Loose files win over the archive.
+        fs::write(dir.join("invaders.h"), b"loose").unwrap();
+        let mut source = RomSource::open(dir.to_str().unwrap(), &manifest).unwrap();
+        assert_eq!(source.read("invaders.h").unwrap(), b"loose");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
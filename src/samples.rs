@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use hound;
+
+use audio::SoundSource;
+use devices::sound::Sound;
+
+// The numbering used by the usual Space Invaders sample sets.
+const SAMPLE_FILES: [(Sound, &str); 10] = [
+    (Sound::Ufo, "0.wav"),
+    (Sound::Shot, "1.wav"),
+    (Sound::PlayerDeath, "2.wav"),
+    (Sound::InvaderDeath, "3.wav"),
+    (Sound::Fleet1, "4.wav"),
+    (Sound::Fleet2, "5.wav"),
+    (Sound::Fleet3, "6.wav"),
+    (Sound::Fleet4, "7.wav"),
+    (Sound::UfoHit, "8.wav"),
+    (Sound::ExtraLife, "9.wav"),
+];
+
+struct Voice {
+    sound: Sound,
+    position: usize,
+    looping: bool,
+}
+
+pub struct SamplePlayer {
+    pub samples: HashMap<Sound, Vec<f32>>,
+    voices: Vec<Voice>,
+}
+
+impl SamplePlayer {
+    // Missing files are reported and left silent.
+    pub fn load(directory: &str, sample_rate: u32) -> SamplePlayer {
+        let mut samples = HashMap::new();
+
+        for &(sound, file_name) in SAMPLE_FILES.iter() {
+            let path = Path::new(directory).join(file_name);
+
+            match load_wav(&path, sample_rate) {
+                Ok(data) => { samples.insert(sound, data); },
+                Err(e) => println!("Unable to load sample {}: {}", path.display(), e),
+            }
+        }
+
+        SamplePlayer {
+            samples,
+            voices: Vec::new(),
+        }
+    }
+}
+
+impl SoundSource for SamplePlayer {
+    fn start(&mut self, sound: Sound) {
+        // Retriggering restarts the sample rather than layering it.
+        self.voices.retain(|voice| voice.sound != sound);
+        self.voices.push(Voice {
+            sound,
+            position: 0,
+            looping: sound == Sound::Ufo,
+        });
+    }
+
+    // One shot samples play to the end, only the looping UFO is cut off.
+    fn stop(&mut self, sound: Sound) {
+        self.voices.retain(|voice| !(voice.looping && voice.sound == sound));
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let samples = &self.samples;
+
+        self.voices.retain(|voice| samples.get(&voice.sound).is_some_and(|data| !data.is_empty()));
+
+        for voice in self.voices.iter_mut() {
+            let data = &samples[&voice.sound];
+
+            for value in out.iter_mut() {
+                if voice.position >= data.len() {
+                    if !voice.looping {
+                        break;
+                    }
+
+                    voice.position = 0;
+                }
+
+                *value += data[voice.position];
+                voice.position += 1;
+            }
+        }
+
+        self.voices.retain(|voice| voice.looping || voice.position < samples[&voice.sound].len());
+    }
+}
+
+// Decodes any integer or float WAV to mono and resamples it to `sample_rate`.
+fn load_wav(path: &Path, sample_rate: u32) -> io::Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path).map_err(io::Error::other)?;
+    let spec = reader.spec();
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>()
+            .collect::<Result<_, _>>()
+            .map_err(io::Error::other)?,
+        hound::SampleFormat::Int => {
+            let scale = (1u32 << (spec.bits_per_sample - 1)) as f32;
+
+            reader.samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<_, _>>()
+                .map_err(io::Error::other)?
+        },
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = interleaved.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    if spec.sample_rate == sample_rate || mono.is_empty() {
+        return Ok(mono);
+    }
+
+    let ratio = spec.sample_rate as f64 / sample_rate as f64;
+    let length = (mono.len() as f64 / ratio) as usize;
+
+    Ok((0..length).map(|index| {
+        let position = index as f64 * ratio;
+        let first = position as usize;
+        let second = (first + 1).min(mono.len() - 1);
+        let fraction = (position - first as f64) as f32;
+
+        mono[first] * (1.0 - fraction) + mono[second] * fraction
+    }).collect())
+}
@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use cpu::Cpu;
+
+const MAGIC: &[u8; 8] = b"R8080SAV";
+// Version 3 added the state of the I/O and memory mapped devices.
+const VERSION: u8 = 3;
+
+impl Cpu {
+    pub fn save_state(&self, file_name: &str) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(file_name)?);
+
+        f.write_all(MAGIC)?;
+        f.write_u8(VERSION)?;
+
+        f.write_all(&[self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l])?;
+        f.write_u16::<LittleEndian>(self.sp)?;
+        f.write_u16::<LittleEndian>(self.pc)?;
+        f.write_u32::<LittleEndian>(self.cycles)?;
+        f.write_u64::<LittleEndian>(self.total_cycles)?;
+        f.write_u64::<LittleEndian>(self.instruction_count)?;
+        f.write_u16::<LittleEndian>(self.last_interrupt)?;
+
+        self.ram.save(&mut f)?;
+        self.io.save(&mut f)?;
+        f.flush()
+    }
+
+    pub fn load_state(&mut self, file_name: &str) -> io::Result<()> {
+        let mut f = BufReader::new(File::open(file_name)?);
+
+        let mut magic = [0; 8];
+        f.read_exact(&mut magic)?;
+
+        if &magic != MAGIC || f.read_u8()? != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a r8080 save state"));
+        }
+
+        let mut regs = [0; 8];
+        f.read_exact(&mut regs)?;
+
+        let sp = f.read_u16::<LittleEndian>()?;
+        let pc = f.read_u16::<LittleEndian>()?;
+        let cycles = f.read_u32::<LittleEndian>()?;
+        let total_cycles = f.read_u64::<LittleEndian>()?;
+        let instruction_count = f.read_u64::<LittleEndian>()?;
+        let last_interrupt = f.read_u16::<LittleEndian>()?;
+
+        // Devices pick up the restored cycle, e.g. for sound events.
+        self.ram.cycle = total_cycles;
+        self.ram.load(&mut f)?;
+        self.io.load(&mut f, total_cycles)?;
+
+        self.a = regs[0];
+        self.f = regs[1];
+        self.b = regs[2];
+        self.c = regs[3];
+        self.d = regs[4];
+        self.e = regs[5];
+        self.h = regs[6];
+        self.l = regs[7];
+
+        self.sp = sp;
+        self.pc = pc;
+        self.cycles = cycles;
+        self.total_cycles = total_cycles;
+        self.instruction_count = instruction_count;
+        self.last_interrupt = last_interrupt;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use devices::panel::{Button, COIN_PULSE_FRAMES};
+    use devices::sound::{Sound, SoundEventKind};
+    use io::IoDevice;
+    use machine;
+    use manifest::Ports;
+    use memmap::MemoryMap;
+    use ram::Sram;
+
+    fn color_board() -> Cpu {
+        let mut ram = Sram::new();
+        ram.map = MemoryMap::invaders();
+
+        let mut cpu = Cpu::headless(ram);
+        machine::invaders_color(&mut cpu, &Ports::default());
+        cpu
+    }
+
+    fn temp_file(name: &str) -> String {
+        env::temp_dir().join(format!("r8080-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn registers_memory_and_devices_round_trip() {
+        let path = temp_file("round-trip.bin");
+        let mut saved = color_board();
+
+        saved.a = 0x12;
+        saved.l = 0x34;
+        saved.sp = 0x23f0;
+        saved.pc = 0x0abc;
+        saved.total_cycles = 123456;
+        saved.ram.write_byte(0x2400, 0xaa);
+        saved.ram.write_byte(0xc405, 0x03);
+        saved.io.output(0x04, 0x0f, 0);
+        saved.io.output(0x04, 0xf0, 0);
+        saved.io.output(0x02, 0x02, 0);
+        saved.io.output(0x03, 0x01, 0);
+        saved.panel.as_ref().unwrap().borrow_mut().update(&[Button::Coin]);
+        saved.watchdog.as_ref().unwrap().borrow_mut().advance(1000);
+        saved.save_state(&path).unwrap();
+
+        let mut loaded = color_board();
+        let events = loaded.sound.as_ref().unwrap().borrow_mut().subscribe();
+        loaded.load_state(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.a, loaded.l, loaded.sp, loaded.pc), (0x12, 0x34, 0x23f0, 0x0abc));
+        assert_eq!(loaded.total_cycles, 123456);
+        assert_eq!(loaded.ram.bytes, saved.ram.bytes);
+
+        assert_eq!(loaded.io.input(0x03, 0), saved.io.input(0x03, 0));
+        assert_eq!(loaded.ram.peek_byte(0xc405), 0x03);
+        assert_eq!(loaded.color_ram.as_ref().unwrap().borrow().color(0x0005), 0x03);
+        assert_eq!(loaded.watchdog.as_ref().unwrap().borrow().elapsed, 1000);
+
+        // The coin pulse carries on where it was.
+        let panel = loaded.panel.as_ref().unwrap();
+        for _ in 1..COIN_PULSE_FRAMES {
+            panel.borrow_mut().update(&[]);
+        }
+        assert_eq!(panel.borrow_mut().input(0x01, 0) & 0x01, 0x01);
+
+        let events: Vec<_> = events.try_iter().map(|event| (event.cycle, event.kind)).collect();
+        assert_eq!(events, vec![(123456, SoundEventKind::Start(Sound::Ufo))]);
+    }
+
+    #[test]
+    fn states_only_load_into_the_same_machine() {
+        let path = temp_file("other-machine.bin");
+        color_board().save_state(&path).unwrap();
+
+        let mut plain = Cpu::headless(Sram::new());
+        let error = plain.load_state(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = temp_file("not-a-state.bin");
+        fs::write(&path, b"R8080SAV\x02").unwrap();
+
+        let error = color_board().load_state(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
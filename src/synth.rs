@@ -0,0 +1,288 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, Read};
+
+use audio::{AudioOutput, SoundSource, WavSink};
+use cpu::CLOCK_RATE;
+use devices::sound::{InvadersSound, Sound};
+use io::IoDevice;
+
+struct Voice {
+    sound: Sound,
+    // Samples since the sound was triggered.
+    time: u32,
+    phase: f32,
+    filter: f32,
+}
+
+// Rough model of the discrete sound circuits on the Space Invaders board.
+// Everything is computed from the sample clock and a shift register noise
+// source, so the output is identical between runs.
+pub struct Synthesizer {
+    sample_rate: f32,
+    voices: Vec<Voice>,
+    noise: u32,
+}
+
+impl Synthesizer {
+    pub fn new(sample_rate: u32) -> Synthesizer {
+        Synthesizer {
+            sample_rate: sample_rate as f32,
+            voices: Vec::new(),
+            noise: 0x1ffff,
+        }
+    }
+
+    // 17 bit LFSR, the same length as the noise generator in the SN76477.
+    fn next_noise(&mut self) -> f32 {
+        let bit = ((self.noise >> 16) ^ (self.noise >> 13)) & 1;
+        self.noise = ((self.noise << 1) | bit) & 0x1ffff;
+
+        if bit != 0 { 1.0 } else { -1.0 }
+    }
+}
+
+impl SoundSource for Synthesizer {
+    fn start(&mut self, sound: Sound) {
+        self.voices.retain(|voice| voice.sound != sound);
+        self.voices.push(Voice {
+            sound,
+            time: 0,
+            phase: 0.0,
+            filter: 0.0,
+        });
+    }
+
+    // Only the UFO runs for as long as its bit is set, the others are one shots.
+    fn stop(&mut self, sound: Sound) {
+        if sound == Sound::Ufo {
+            self.voices.retain(|voice| voice.sound != Sound::Ufo);
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let rate = self.sample_rate;
+
+        for value in out.iter_mut() {
+            let noise = self.next_noise();
+
+            for voice in self.voices.iter_mut() {
+                *value += voice.sample(noise, rate);
+            }
+
+            self.voices.retain(|voice| !voice.finished(rate));
+        }
+    }
+}
+
+impl Voice {
+    fn sample(&mut self, noise: f32, rate: f32) -> f32 {
+        let t = self.time as f32 / rate;
+        self.time += 1;
+
+        match self.sound {
+            // Triangle wave warbling around 700Hz.
+            Sound::Ufo => {
+                let frequency = 700.0 + 250.0 * (2.0 * PI * 7.0 * t).sin();
+                0.25 * self.triangle(frequency, rate)
+            },
+            // Falling tone with some noise mixed in.
+            Sound::Shot => {
+                let frequency = 1200.0 * (-8.0 * t).exp() + 150.0;
+                let envelope = (1.0 - t / duration(Sound::Shot)).max(0.0).powi(2);
+                0.3 * envelope * (0.6 * self.square(frequency, rate) + 0.4 * noise)
+            },
+            // Filtered noise whose cutoff drops as it fades.
+            Sound::PlayerDeath => {
+                let envelope = (-3.0 * t).exp();
+                0.5 * envelope * self.low_pass(noise, 200.0 + 1500.0 * envelope, rate)
+            },
+            Sound::InvaderDeath => {
+                let envelope = (-10.0 * t).exp();
+                0.4 * envelope * self.low_pass(noise, 3000.0, rate)
+            },
+            // The four march notes are short low thumps.
+            Sound::Fleet1 | Sound::Fleet2 | Sound::Fleet3 | Sound::Fleet4 => {
+                let frequency = match self.sound {
+                    Sound::Fleet1 => 110.0,
+                    Sound::Fleet2 => 98.0,
+                    Sound::Fleet3 => 87.0,
+                    _ => 82.0,
+                };
+                let envelope = (1.0 - t / duration(self.sound)).max(0.0);
+                let square = self.square(frequency, rate);
+                0.4 * envelope * self.low_pass(square, 400.0, rate)
+            },
+            // Falling warble.
+            Sound::UfoHit => {
+                let frequency = 1000.0 * (-2.0 * t).exp() + 200.0 + 80.0 * (2.0 * PI * 20.0 * t).sin();
+                let envelope = (1.0 - t / duration(Sound::UfoHit)).max(0.0);
+                0.3 * envelope * self.triangle(frequency, rate)
+            },
+            // Four beeps a second.
+            Sound::ExtraLife => {
+                let gate = if (t * 4.0).fract() < 0.5 { 1.0 } else { 0.0 };
+                0.25 * gate * self.square(480.0, rate)
+            },
+        }
+    }
+
+    fn finished(&self, rate: f32) -> bool {
+        self.sound != Sound::Ufo && self.time as f32 / rate >= duration(self.sound)
+    }
+
+    fn advance(&mut self, frequency: f32, rate: f32) -> f32 {
+        self.phase = (self.phase + frequency / rate).fract();
+        self.phase
+    }
+
+    fn square(&mut self, frequency: f32, rate: f32) -> f32 {
+        if self.advance(frequency, rate) < 0.5 { 1.0 } else { -1.0 }
+    }
+
+    fn triangle(&mut self, frequency: f32, rate: f32) -> f32 {
+        let phase = self.advance(frequency, rate);
+        4.0 * (phase - 0.5).abs() - 1.0
+    }
+
+    fn low_pass(&mut self, input: f32, cutoff: f32, rate: f32) -> f32 {
+        let alpha = 1.0 - (-2.0 * PI * cutoff / rate).exp();
+        self.filter += alpha * (input - self.filter);
+        self.filter
+    }
+}
+
+// Length of the one shot sounds in seconds.
+fn duration(sound: Sound) -> f32 {
+    match sound {
+        Sound::Ufo => f32::INFINITY,
+        Sound::Shot => 0.3,
+        Sound::PlayerDeath => 1.2,
+        Sound::InvaderDeath => 0.35,
+        Sound::Fleet1 | Sound::Fleet2 | Sound::Fleet3 | Sound::Fleet4 => 0.12,
+        Sound::UfoHit => 1.0,
+        Sound::ExtraLife => 1.2,
+    }
+}
+
+// Port writes for a fixed tour of every sound, as (port, value, seconds).
+const DEMO: [(u8, u8, f32); 20] = [
+    (0x03, 0x20, 0.0),
+    (0x05, 0x01, 0.2),
+    (0x05, 0x02, 0.6),
+    (0x05, 0x04, 1.0),
+    (0x05, 0x08, 1.4),
+    (0x05, 0x00, 1.6),
+    (0x03, 0x22, 2.0),
+    (0x03, 0x20, 2.1),
+    (0x03, 0x28, 2.6),
+    (0x03, 0x20, 2.7),
+    (0x03, 0x21, 3.2),
+    (0x03, 0x20, 4.4),
+    (0x05, 0x10, 4.6),
+    (0x05, 0x00, 4.7),
+    (0x03, 0x24, 5.8),
+    (0x03, 0x20, 5.9),
+    (0x03, 0x30, 7.2),
+    (0x03, 0x20, 7.3),
+    (0x03, 0x00, 8.6),
+    (0x03, 0x00, 9.0),
+];
+
+// Renders the demo without a window or ROM and returns a hash of the WAV file,
+// to compare renders on one machine. Other platforms may round differently.
+pub fn render_demo(file_name: &str, sample_rate: u32) -> io::Result<u64> {
+    let mut sound = InvadersSound::new();
+    let sink = Box::new(WavSink::new(file_name, sample_rate)?);
+    let mut output = AudioOutput::new(&mut sound, Box::new(Synthesizer::new(sample_rate)), sink, sample_rate);
+
+    let mut cycle = 0;
+
+    for &(port, value, seconds) in DEMO.iter() {
+        cycle = (seconds as f64 * CLOCK_RATE as f64) as u64;
+        sound.output(port, value, cycle);
+        output.update(cycle)?;
+    }
+
+    output.finish(cycle)?;
+
+    let mut bytes = Vec::new();
+    File::open(file_name)?.read_to_end(&mut bytes)?;
+
+    Ok(fnv1a(&bytes))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(sound: Sound, seconds: f32) -> Vec<f32> {
+        let mut synth = Synthesizer::new(22050);
+        let mut out = vec![0.0; (22050.0 * seconds) as usize];
+
+        synth.start(sound);
+        synth.render(&mut out);
+        out
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        assert_eq!(render(Sound::PlayerDeath, 0.5), render(Sound::PlayerDeath, 0.5));
+    }
+
+    // Peak and RMS of the first half second of every sound. Update after
+    // deliberate changes to the synthesizer. They are compared with a tolerance
+    // because sin and exp come from the platform's libm and may differ in the
+    // last bit, so a hash of the output would not match everywhere.
+    const LEVELS: [(Sound, f32, f32); 10] = [
+        (Sound::Ufo, 0.2500, 0.1443),
+        (Sound::Shot, 0.2993, 0.0746),
+        (Sound::PlayerDeath, 0.4985, 0.1217),
+        (Sound::InvaderDeath, 0.3984, 0.0846),
+        (Sound::ExtraLife, 0.2500, 0.1768),
+        (Sound::Fleet1, 0.3913, 0.1027),
+        (Sound::Fleet2, 0.3913, 0.1038),
+        (Sound::Fleet3, 0.3913, 0.1049),
+        (Sound::Fleet4, 0.3913, 0.1054),
+        (Sound::UfoHit, 0.2958, 0.1323),
+    ];
+
+    #[test]
+    fn sounds_keep_their_levels() {
+        for &(sound, peak, rms) in LEVELS.iter() {
+            let out = render(sound, 0.5);
+            let rendered_peak = out.iter().fold(0.0f32, |peak, value| peak.max(value.abs()));
+            let rendered_rms = (out.iter().map(|value| value * value).sum::<f32>() / out.len() as f32).sqrt();
+
+            assert!((rendered_peak - peak).abs() < 1e-3 && (rendered_rms - rms).abs() < 1e-3,
+                "{:?} has peak {:.4} and RMS {:.4}", sound, rendered_peak, rendered_rms);
+        }
+    }
+
+    #[test]
+    fn every_sound_is_audible() {
+        for &sound in [Sound::Ufo, Sound::Shot, Sound::PlayerDeath, Sound::InvaderDeath, Sound::ExtraLife,
+                       Sound::Fleet1, Sound::Fleet2, Sound::Fleet3, Sound::Fleet4, Sound::UfoHit].iter() {
+            let peak = render(sound, 0.1).iter().fold(0.0f32, |peak, value| peak.max(value.abs()));
+            assert!(peak > 0.05, "{:?} is silent", sound);
+        }
+    }
+
+    #[test]
+    fn one_shots_end_and_the_ufo_loops() {
+        let mut synth = Synthesizer::new(22050);
+        let mut out = vec![0.0; 22050 * 2];
+
+        synth.start(Sound::Shot);
+        synth.start(Sound::Ufo);
+        synth.render(&mut out);
+        assert_eq!(synth.voices.len(), 1);
+
+        synth.stop(Sound::Ufo);
+        assert!(synth.voices.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use opcode::Opcode;
+
+pub const DEFAULT_TRACE_DEPTH: usize = 64;
+
+#[derive(Clone, Copy)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u8,
+    pub operands: [u8; 2],
+
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+}
+
+// Fixed size ring buffer of the most recently executed instructions.
+pub struct Trace {
+    entries: Vec<TraceEntry>,
+    capacity: usize,
+    next: usize,
+}
+
+impl Trace {
+    pub fn new(capacity: usize) -> Trace {
+        Trace {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+        }
+
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    // Oldest entry first.
+    pub fn entries(&self) -> Vec<&TraceEntry> {
+        let (newer, older) = self.entries.split_at(self.next);
+
+        older.iter().chain(newer.iter()).collect()
+    }
+
+    pub fn backtrace(&self) -> String {
+        let mut out = String::new();
+
+        for entry in self.entries() {
+            out.push_str(&format!("{:?}\n", entry));
+        }
+
+        out
+    }
+}
+
+impl fmt::Debug for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = Opcode::new(self.opcode);
+        let text = op.disassemble(self.operands[0], self.operands[1]);
+
+        write!(
+            f,
+            "{:#06x}: {:<16} A: {:#04x} B: {:#04x} C: {:#04x} D: {:#04x} E: {:#04x} H: {:#04x} L: {:#04x} F: {:#04x} SP: {:#06x}",
+            self.pc, text, self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.f, self.sp
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pc: u16) -> TraceEntry {
+        TraceEntry {
+            pc,
+            opcode: 0x00,
+            operands: [0x00, 0x00],
+
+            a: 0x00,
+            f: 0x00,
+            b: 0x00,
+            c: 0x00,
+            d: 0x00,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
+            sp: 0x2400,
+        }
+    }
+
+    fn pcs(trace: &Trace) -> Vec<u16> {
+        trace.entries().iter().map(|entry| entry.pc).collect()
+    }
+
+    #[test]
+    fn entries_are_oldest_first_before_the_buffer_fills() {
+        let mut trace = Trace::new(4);
+        trace.record(entry(1));
+        trace.record(entry(2));
+
+        assert_eq!(pcs(&trace), vec![1, 2]);
+    }
+
+    #[test]
+    fn recording_wraps_around_and_drops_the_oldest() {
+        let mut trace = Trace::new(3);
+
+        for pc in 0..8 {
+            trace.record(entry(pc));
+        }
+
+        assert_eq!(pcs(&trace), vec![5, 6, 7]);
+
+        trace.record(entry(8));
+        assert_eq!(pcs(&trace), vec![6, 7, 8]);
+    }
+
+    #[test]
+    fn zero_depth_keeps_nothing() {
+        let mut trace = Trace::new(0);
+        trace.record(entry(1));
+
+        assert!(trace.entries().is_empty());
+        assert_eq!(trace.backtrace(), "");
+    }
+}
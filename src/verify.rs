@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crc32fast;
+use sha1::Sha1;
+
+use manifest::{Manifest, RomChunk};
+use romsource::RomSource;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Checksums {
+    pub crc32: u32,
+    pub sha1: String,
+}
+
+impl Checksums {
+    pub fn new(bytes: &[u8]) -> Checksums {
+        Checksums {
+            crc32: crc32fast::hash(bytes),
+            sha1: Sha1::from(bytes).digest().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Status {
+    Good,
+    // The manifest has no checksums for this chunk, only the size was checked.
+    Unverified,
+    Missing,
+    // Present but could not be read, e.g. a corrupt zip member.
+    Unreadable(String),
+    WrongSize(usize),
+    BadDump(Checksums),
+}
+
+// How far a directory or archive matches a known set, best first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Match {
+    // Every chunk has the known checksums.
+    Verified,
+    // Usable, but some chunks have no known checksums, so only their file
+    // names and sizes matched and a bad dump would go unnoticed.
+    Unverified,
+    Incomplete,
+}
+
+impl Match {
+    pub fn of(statuses: &[Status]) -> Match {
+        if statuses.iter().all(|status| *status == Status::Good) {
+            Match::Verified
+        } else if statuses.iter().all(|status| status.is_usable()) {
+            Match::Unverified
+        } else {
+            Match::Incomplete
+        }
+    }
+}
+
+impl Status {
+    // Good enough to run, not necessarily a good dump.
+    pub fn is_usable(&self) -> bool {
+        *self == Status::Good || *self == Status::Unverified
+    }
+
+    pub fn describe(&self, rom: &RomChunk) -> String {
+        match *self {
+            Status::Good => String::from("ok"),
+            Status::Unverified => String::from("unverified, right size but no known checksum"),
+            Status::Missing => String::from("missing"),
+            Status::Unreadable(ref error) => format!("unreadable, {}", error),
+            Status::WrongSize(size) => format!("wrong size, {:#x} bytes instead of {:#x}", size, rom.size),
+            Status::BadDump(ref checksums) => format!("bad dump, crc32 {:08x} sha1 {} expected crc32 {} sha1 {}",
+                checksums.crc32, checksums.sha1,
+                rom.crc32.as_ref().map_or("-", |crc| crc.as_str()),
+                rom.sha1.as_ref().map_or("-", |sha1| sha1.as_str())),
+        }
+    }
+}
+
+pub fn check(rom: &RomChunk, bytes: &[u8]) -> Status {
+    if bytes.len() != rom.size {
+        return Status::WrongSize(bytes.len());
+    }
+
+    if rom.crc32.is_none() && rom.sha1.is_none() {
+        return Status::Unverified;
+    }
+
+    let checksums = Checksums::new(bytes);
+
+    let crc_ok = rom.crc32.as_ref().is_none_or(|crc| u32::from_str_radix(crc, 16) == Ok(checksums.crc32));
+    let sha1_ok = rom.sha1.as_ref().is_none_or(|sha1| sha1.eq_ignore_ascii_case(&checksums.sha1));
+
+    if crc_ok && sha1_ok {
+        Status::Good
+    } else {
+        Status::BadDump(checksums)
+    }
+}
+
+// Status of every chunk of a manifest in a directory or zip archive.
+pub fn verify_dir(manifest: &Manifest, path: &str) -> Vec<Status> {
+    let mut source = match RomSource::open(path, manifest) {
+        Ok(source) => source,
+        Err(_) => return vec![Status::Missing; manifest.roms.len()],
+    };
+
+    manifest.roms.iter().map(|rom| {
+        match source.read(&rom.file) {
+            Ok(bytes) => check(rom, &bytes),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Status::Missing,
+            Err(e) => Status::Unreadable(e.to_string()),
+        }
+    }).collect()
+}
+
+pub fn report(manifest: &Manifest, statuses: &[Status]) -> String {
+    let mut out = format!("{} ({}):\n", manifest.name, manifest.description);
+
+    for (rom, status) in manifest.roms.iter().zip(statuses.iter()) {
+        out.push_str(&format!("  {:<16} {:#06x} {}\n", rom.file, rom.offset, status.describe(rom)));
+    }
+
+    out
+}
+
+// Known sets that have at least one of their files in the directory or
+// archive, verified sets first, then sets only matched by name and size.
+pub fn identify_dir(path: &str) -> Vec<(Manifest, Vec<Status>)> {
+    let mut found: Vec<(Manifest, Vec<Status>)> = Manifest::builtin().into_iter()
+        .map(|manifest| {
+            let statuses = verify_dir(&manifest, path);
+            (manifest, statuses)
+        })
+        .filter(|(_, statuses)| statuses.iter().any(|status| *status != Status::Missing))
+        .collect();
+
+    found.sort_by(|(_, a), (_, b)| {
+        let unusable = |statuses: &[Status]| statuses.iter().filter(|status| !status.is_usable()).count();
+        Match::of(a).cmp(&Match::of(b)).then(unusable(a).cmp(&unusable(b)))
+    });
+    found
+}
+
+// Known sets whose chunks, put back to back in manifest order, make up the image.
+pub fn identify_image(bytes: &[u8]) -> Vec<Manifest> {
+    Manifest::builtin().into_iter().filter(|manifest| {
+        let total: usize = manifest.roms.iter().map(|rom| rom.size).sum();
+
+        if total != bytes.len() || manifest.roms.iter().all(|rom| rom.crc32.is_none() && rom.sha1.is_none()) {
+            return false;
+        }
+
+        let mut offset = 0;
+
+        manifest.roms.iter().all(|rom| {
+            let chunk = &bytes[offset..offset + rom.size];
+            offset += rom.size;
+            check(rom, chunk) == Status::Good
+        })
+    }).collect()
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const INVADERS: &[u8] = include_bytes!("invaders.rom");
+
+    fn temp_dir(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("r8080-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+
+        for &(file, bytes) in files {
+            fs::write(dir.join(file), bytes).unwrap();
+        }
+
+        dir
+    }
+
+    fn chunk(crc32: Option<&str>) -> RomChunk {
+        RomChunk {
+            file: String::from("test"),
+            offset: 0,
+            size: 4,
+            crc32: crc32.map(String::from),
+            sha1: None,
+        }
+    }
+
+    #[test]
+    fn check_compares_size_then_checksums() {
+        let bytes = [1, 2, 3, 4];
+        let crc = format!("{:08x}", crc32fast::hash(&bytes));
+
+        assert_eq!(check(&chunk(Some(&crc)), &bytes), Status::Good);
+        assert_eq!(check(&chunk(Some(&crc.to_uppercase())), &bytes), Status::Good);
+        assert_eq!(check(&chunk(Some(&crc)), &bytes[..3]), Status::WrongSize(3));
+        assert_eq!(check(&chunk(None), &bytes), Status::Unverified);
+
+        match check(&chunk(Some(&crc)), &[1, 2, 3, 5]) {
+            Status::BadDump(checksums) => assert_eq!(checksums, Checksums::new(&[1, 2, 3, 5])),
+            status => panic!("expected a bad dump, got {:?}", status),
+        }
+    }
+
+    #[test]
+    fn identify_dir_verifies_split_invaders() {
+        let dir = temp_dir("split", &[
+            ("invaders.h", &INVADERS[0x0000..0x0800]),
+            ("invaders.g", &INVADERS[0x0800..0x1000]),
+            ("invaders.f", &INVADERS[0x1000..0x1800]),
+            ("invaders.e", &INVADERS[0x1800..0x2000]),
+        ]);
+
+        let found = identify_dir(dir.to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found[0].0.name, "invaders");
+        assert_eq!(Match::of(&found[0].1), Match::Verified);
+    }
+
+    #[test]
+    fn identify_dir_flags_bad_dumps_and_name_only_matches() {
+        let mut bad = INVADERS[0x1800..0x2000].to_vec();
+        bad[0x10] ^= 0xff;
+        let blank = [0u8; 0x800];
+
+        let dir = temp_dir("bad", &[
+            ("invaders.h", &INVADERS[0x0000..0x0800]),
+            ("invaders.g", &INVADERS[0x0800..0x1000]),
+            ("invaders.f", &INVADERS[0x1000..0x1800]),
+            ("invaders.e", &bad),
+            ("tn01", &blank), ("tn02", &blank), ("tn03", &blank), ("tn04", &blank), ("tn05-1", &blank),
+        ]);
+
+        let found = identify_dir(dir.to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let matches: Vec<(&str, Match)> = found.iter().map(|(manifest, statuses)| (manifest.name.as_str(), Match::of(statuses))).collect();
+        assert_eq!(matches, vec![("ballbomb", Match::Unverified), ("invaders", Match::Incomplete)]);
+
+        match found[1].1[3] {
+            Status::BadDump(_) => (),
+            ref status => panic!("expected a bad dump, got {:?}", status),
+        }
+    }
+
+    #[test]
+    fn identify_image_needs_checksums() {
+        let names: Vec<String> = identify_image(INVADERS).into_iter().map(|manifest| manifest.name).collect();
+        assert_eq!(names, vec!["invaders", "invaders-merged"]);
+
+        let mut bad = INVADERS.to_vec();
+        bad[0] ^= 0xff;
+        assert!(identify_image(&bad).is_empty());
+
+        // Right size for Balloon Bomber, which has no known checksums.
+        assert!(identify_image(&[0; 0x2800]).is_empty());
+    }
+}